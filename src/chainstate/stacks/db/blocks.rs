@@ -1019,6 +1019,37 @@ impl StacksChainState {
         Ok(Some(microblocks))
     }
 
+    /// Load the microblocks that a processed anchored block confirmed, in sequence order, along
+    /// with the index block hash of the anchored block they were built on.  Returns None if the
+    /// block is unknown or confirmed no microblocks.
+    pub fn load_confirmed_microblocks(&self, burn_header_hash: &BurnchainHeaderHash, anchored_block_hash: &BlockHeaderHash) -> Result<Option<(BlockHeaderHash, Vec<StacksMicroblock>)>, Error> {
+        let sql = "SELECT * FROM staging_blocks WHERE anchored_block_hash = ?1 AND burn_header_hash = ?2".to_string();
+        let args: &[&dyn ToSql] = &[&anchored_block_hash, &burn_header_hash];
+        let block = match query_rows::<StagingBlock, _>(&self.blocks_db, &sql, args).map_err(Error::DBError)?.pop() {
+            Some(block) => block,
+            None => {
+                return Ok(None);
+            }
+        };
+
+        if block.parent_microblock_hash == EMPTY_MICROBLOCK_PARENT_HASH && block.parent_microblock_seq == 0 {
+            return Ok(None);
+        }
+
+        let mut microblocks = match StacksChainState::load_staging_microblock_stream(&self.blocks_db, &self.blocks_path, &block.parent_burn_header_hash,
+                                                                                     &block.parent_anchored_block_hash, block.parent_microblock_seq)? {
+            Some(microblocks) => microblocks,
+            None => {
+                return Ok(None);
+            }
+        };
+
+        // a longer stream may have been stored on behalf of another fork
+        microblocks.retain(|microblock| microblock.header.sequence <= block.parent_microblock_seq);
+        let parent_index_block_hash = StacksBlockHeader::make_index_block_hash(&block.parent_burn_header_hash, &block.parent_anchored_block_hash);
+        Ok(Some((parent_index_block_hash, microblocks)))
+    }
+
     /// Get an anchored block's parent block header.
     /// Doesn't matter if it's staging or not.
     pub fn load_parent_block_header<'a>(burn_tx: &mut BurnDBTx<'a>, blocks_conn: &DBConn, blocks_path: &String, burn_header_hash: &BurnchainHeaderHash, anchored_block_hash: &BlockHeaderHash) -> Result<Option<(StacksBlockHeader, BurnchainHeaderHash)>, Error> {
@@ -105,6 +105,8 @@ pub struct STXTransferEventData {
 }
 
 impl STXTransferEventData {
+    /// Fields: `sender`, `recipient`, and `amount` in microSTX.  `amount` is a decimal string,
+    /// since a u128 does not fit in a JSON number.
     pub fn json_serialize(&self) -> serde_json::Value {
         json!({
             "sender": format!("{}",self.sender),
@@ -121,6 +123,7 @@ pub struct STXMintEventData {
 }
 
 impl STXMintEventData {
+    /// Fields: `recipient`, and `amount` in microSTX as a decimal string.
     pub fn json_serialize(&self) -> serde_json::Value {
         json!({
             "recipient": format!("{}",self.recipient),
@@ -136,6 +139,7 @@ pub struct STXBurnEventData {
 }
 
 impl STXBurnEventData {
+    /// Fields: `sender`, and `amount` in microSTX as a decimal string.
     pub fn json_serialize(&self) -> serde_json::Value {
         json!({
             "sender": format!("{}",self.sender),
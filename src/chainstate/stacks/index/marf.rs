@@ -765,8 +765,8 @@ impl MARF {
     /// Finish writing the next trie in the MARF.  This persists all changes.
     pub fn commit(&mut self) -> Result<(), Error> {
         match self.open_chain_tip.take() {
-            Some(_tip) => {
-                self.storage.flush()?;
+            Some(tip) => {
+                self.storage.flush_at_height(tip.height)?;
             },
             None => {}
         };
@@ -791,8 +791,8 @@ impl MARF {
     /// block hash to something other than what we opened it as.  This persists all changes.
     pub fn commit_to(&mut self, real_bhh: &BlockHeaderHash) -> Result<(), Error> {
         match self.open_chain_tip.take() {
-            Some(_tip) => {
-                self.storage.flush_to(real_bhh)?;
+            Some(tip) => {
+                self.storage.flush_to_at_height(real_bhh, tip.height)?;
             },
             None => {}
         };
@@ -1050,6 +1050,40 @@ mod test {
         }
     }
     
+    #[test]
+    fn marf_num_blocks_cached() {
+        let f = TrieFileStorage::new_memory().unwrap();
+        let block_header = BlockHeaderHash::from_bytes(&[0u8; 32]).unwrap();
+        let mut marf = MARF::from_storage(f);
+
+        assert_eq!(marf.borrow_storage_backend().num_blocks_cached(), marf.borrow_storage_backend().num_blocks());
+        marf.begin(&TrieFileStorage::block_sentinel(), &block_header).unwrap();
+
+        for i in 0..10 {
+            let path_bytes = [i as u8,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31];
+            let path = TriePath::from_bytes(&path_bytes).unwrap();
+            let prior_block_header = BlockHeaderHash::from_bytes(&[i as u8; 32]).unwrap();
+            let next_block_header = BlockHeaderHash::from_bytes(&[i+1 as u8; 32]).unwrap();
+            marf.commit().unwrap();
+            assert_eq!(marf.borrow_storage_backend().num_blocks_cached(), marf.borrow_storage_backend().num_blocks());
+
+            marf.begin(&prior_block_header, &next_block_header).unwrap();
+            assert_eq!(marf.borrow_storage_backend().num_blocks_cached(), marf.borrow_storage_backend().num_blocks());
+
+            let value = TrieLeaf::new(&vec![], &[i as u8; 40].to_vec());
+            marf.insert_raw(path, value).unwrap();
+        }
+
+        marf.commit().unwrap();
+        assert_eq!(marf.borrow_storage_backend().num_blocks_cached(), 11);
+        assert_eq!(marf.borrow_storage_backend().num_blocks(), 11);
+
+        // deleting the newest trie lowers the count
+        marf.borrow_storage_backend().delete_tries(&[BlockHeaderHash::from_bytes(&[10u8; 32]).unwrap()]).unwrap();
+        assert_eq!(marf.borrow_storage_backend().num_blocks_cached(), 10);
+        assert_eq!(marf.borrow_storage_backend().num_blocks(), 10);
+    }
+
     #[test]
     fn marf_insert_leaf_sequence_100() {
         let path = "/tmp/rust_marf_insert_leaf_sequence_100";
@@ -2003,6 +2037,26 @@ mod test {
         }
     }
 
+    #[test]
+    fn marf_commit_records_block_heights() {
+        let f = TrieFileStorage::new_memory().unwrap();
+        let mut marf = MARF::from_storage(f);
+        let blocks: Vec<_> = (1..4u8).map(|i| BlockHeaderHash([i; 32])).collect();
+
+        marf.begin(&TrieFileStorage::block_sentinel(), &blocks[0]).unwrap();
+        marf.commit().unwrap();
+        marf.begin(&blocks[0], &blocks[1]).unwrap();
+        marf.commit().unwrap();
+        // a block built under a placeholder hash keeps its height when retargeted
+        marf.begin(&blocks[1], &BlockHeaderHash([0xffu8; 32])).unwrap();
+        marf.commit_to(&blocks[2]).unwrap();
+
+        let storage = marf.borrow_storage_backend();
+        let block_ids: Vec<_> = blocks.iter().map(|bhh| storage.get_block_identifier(bhh).unwrap()).collect();
+        assert_eq!(storage.get_block_identifiers_in_height_range(0, 10).unwrap(), block_ids);
+        assert_eq!(storage.get_block_identifiers_in_height_range(1, 1).unwrap(), vec![block_ids[1]]);
+    }
+
     #[test]
     #[ignore]
     fn marf_insert_flush_to_different_block() {
@@ -238,7 +238,9 @@ pub enum Error {
     WriteNotBegunError,
     CursorError(node::CursorError),
     RestoreMarfBlockError(Box<Error>),
-    NonMatchingForks(BlockHeaderHash, BlockHeaderHash)
+    NonMatchingForks(BlockHeaderHash, BlockHeaderHash),
+    SchemaVersionError(u32, u32),
+    BlockAlreadyExists(BlockHeaderHash)
 }
 
 impl From<io::Error> for Error {
@@ -284,6 +286,8 @@ impl fmt::Display for Error {
             Error::RestoreMarfBlockError(_) => write!(f, "Failed to restore previous open block during block header check"),
             Error::NonMatchingForks(_, _) => write!(f, "The supplied blocks are not in the same fork"),
             Error::RequestedIdentifierForExtensionTrie => write!(f, "BUG: MARF requested the identifier for a RAM trie"),
+            Error::SchemaVersionError(found, supported) => write!(f, "MARF database schema version {} is newer than the supported version {}", found, supported),
+            Error::BlockAlreadyExists(ref bhh) => write!(f, "Block {} has already been written to the MARF", bhh),
         }
     }
 }
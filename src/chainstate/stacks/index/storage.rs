@@ -29,6 +29,7 @@ use std::io::{
     BufWriter,
 };
 
+use std::cmp;
 use std::char::from_digit;
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
@@ -453,6 +454,12 @@ pub struct TrieSqlHashMapCursor <'a> {
     cache: &'a mut HashMap<u32, BlockHeaderHash>
 }
 
+impl <'a> TrieSqlHashMapCursor<'a> {
+    pub fn new(db: &'a Connection, cache: &'a mut HashMap<u32, BlockHeaderHash>) -> TrieSqlHashMapCursor<'a> {
+        TrieSqlHashMapCursor { db, cache }
+    }
+}
+
 impl NodeHashReader for TrieSqlCursor<'_> {
     fn read_node_hash_bytes<W: Write>(&mut self, ptr: &TriePtr, w: &mut W) -> Result<(), Error> {
         trie_sql::read_node_hash_bytes(self.db, w, self.block_id, ptr)
@@ -486,6 +493,10 @@ pub struct TrieFileStorage {
 
     block_hash_cache: HashMap<u32, BlockHeaderHash>,
 
+    // in-RAM copy of trie_sql::count_blocks(), loaded on first use and
+    //   maintained as tries are flushed to marf_data
+    block_count_cache: Option<u32>,
+
     // used in testing in order to short-circuit block-height lookups
     //   when the trie struct is tested outside of marf.rs usage
     #[cfg(test)]
@@ -494,11 +505,9 @@ pub struct TrieFileStorage {
 
 impl TrieFileStorage {
     pub fn new(dir_path: &str) -> Result<TrieFileStorage, Error> {
-        let mut db = Connection::open(dir_path)?;
+        let db = trie_sql::open(dir_path)?;
         let dir_path = dir_path.to_string();
 
-        trie_sql::create_tables_if_needed(&mut db)?;
-
         test_debug!("Opened TrieFileStorage {};", dir_path);
 
         let ret = TrieFileStorage {
@@ -520,6 +529,7 @@ impl TrieFileStorage {
 
             trie_ancestor_hash_bytes_cache: None,
             block_hash_cache: HashMap::new(),
+            block_count_cache: None,
   
             miner_tip: None,
             
@@ -733,6 +743,11 @@ impl TrieFileStorage {
         trie_sql::get_block_identifier(&self.db, bhh).ok()
     }
 
+    /// Identifiers of the committed blocks with heights in `[lo, hi]`, from every fork.
+    pub fn get_block_identifiers_in_height_range(&self, lo: u64, hi: u64) -> Result<Vec<u32>, Error> {
+        trie_sql::get_block_identifiers_in_height_range(&self.db, lo, hi)
+    }
+
     pub fn get_cur_block_identifier(&mut self) -> Result<u32, Error> {
         if let Some((ref last_extended, _)) = self.last_extended {
             if &self.cur_block == last_extended {
@@ -782,6 +797,7 @@ impl TrieFileStorage {
 
         // blow away db
         trie_sql::clear_tables(&mut self.db)?;
+        self.block_count_cache = None;
 
         match self.last_extended {
             Some((_, ref mut trie_storage)) => trie_storage.format()?,
@@ -943,7 +959,7 @@ impl TrieFileStorage {
         self.write_nodetype(ptr, &node_type, hash)
     }
     
-    fn inner_flush(&mut self, flush_options: FlushOptions) -> Result<(), Error> {
+    fn inner_flush(&mut self, flush_options: FlushOptions, block_height: Option<u64>) -> Result<(), Error> {
         // save the currently-bufferred Trie to disk, and atomically put it into place (possibly to
         // a different block than the one opened, as indicated by final_bhh).
         // Runs once -- subsequent calls are no-ops.
@@ -962,10 +978,15 @@ impl TrieFileStorage {
 
             debug!("Flush: {} to {}", bhh, flush_options);
 
+            let is_committed = match flush_options {
+                FlushOptions::MinedTable(_) => false,
+                _ => true
+            };
+
             let tx = self.db.transaction()?;
             let block_id = match flush_options {
                 FlushOptions::CurrentHeader => {
-                    trie_sql::write_trie_blob(&tx, bhh, &buffer)?
+                    trie_sql::write_trie_blob_at_height(&tx, bhh, &buffer, block_height)?
                 },
                 FlushOptions::NewHeader(real_bhh) => {
                     // If we opened a block with a given hash, but want to store it as a block with a *different*
@@ -982,7 +1003,7 @@ impl TrieFileStorage {
                         self.trie_ancestor_hash_bytes_cache = None;
                         self.cur_block = real_bhh.clone();
                     }
-                    trie_sql::write_trie_blob(&tx, real_bhh, &buffer)?
+                    trie_sql::write_trie_blob_at_height(&tx, real_bhh, &buffer, block_height)?
                 },
                 FlushOptions::MinedTable(real_bhh) => {
                     trie_sql::write_trie_blob_to_mined(&tx, real_bhh, &buffer)?
@@ -992,6 +1013,12 @@ impl TrieFileStorage {
             trie_sql::drop_lock(&tx, bhh)?;
             tx.commit()?;
 
+            if is_committed {
+                if let Some(count) = self.block_count_cache {
+                    self.block_count_cache = Some(cmp::max(count, block_id));
+                }
+            }
+
             debug!("Flush: identifier of {} is {}", flush_options, block_id);
         }
 
@@ -999,15 +1026,40 @@ impl TrieFileStorage {
     }
 
     pub fn flush(&mut self) -> Result<(), Error> {
-        self.inner_flush(FlushOptions::CurrentHeader)
+        self.inner_flush(FlushOptions::CurrentHeader, None)
+    }
+
+    /// Same as flush(), but records the block's height alongside its trie.
+    pub fn flush_at_height(&mut self, block_height: u32) -> Result<(), Error> {
+        self.inner_flush(FlushOptions::CurrentHeader, Some(block_height as u64))
     }
 
     pub fn flush_to(&mut self, bhh: &BlockHeaderHash) -> Result<(), Error> {
-        self.inner_flush(FlushOptions::NewHeader(bhh))
+        self.inner_flush(FlushOptions::NewHeader(bhh), None)
+    }
+
+    /// Same as flush_to(), but records the block's height alongside its trie.
+    pub fn flush_to_at_height(&mut self, bhh: &BlockHeaderHash, block_height: u32) -> Result<(), Error> {
+        self.inner_flush(FlushOptions::NewHeader(bhh), Some(block_height as u64))
     }
 
     pub fn flush_mined(&mut self, bhh: &BlockHeaderHash) -> Result<(), Error> {
-        self.inner_flush(FlushOptions::MinedTable(bhh))
+        self.inner_flush(FlushOptions::MinedTable(bhh), None)
+    }
+
+    /// Delete committed tries, all or none, via trie_sql::delete_trie_blobs() (whose caveats
+    /// apply), and drop everything this storage cached about the blocks in marf_data.
+    pub fn delete_tries(&mut self, bhhs: &[BlockHeaderHash]) -> Result<(), Error> {
+        trie_sql::delete_trie_blobs(&mut self.db, bhhs)?;
+
+        self.block_count_cache = None;
+        self.block_hash_cache.clear();
+        self.trie_ancestor_hash_bytes_cache = None;
+        if bhhs.contains(&self.cur_block) {
+            self.cur_block = TrieFileStorage::block_sentinel();
+            self.cur_block_id = None;
+        }
+        Ok(())
     }
 
     pub fn drop_extending_trie(&mut self) {
@@ -1041,4 +1093,27 @@ impl TrieFileStorage {
         result + (trie_sql::count_blocks(&self.db)
                   .expect("Corruption: SQL Error on a non-fallible query.") as usize)
     }
+
+    /// Same as num_blocks(), but only queries marf_data on first use.  Afterwards, the count is
+    /// kept up to date in RAM as tries are flushed, and reloaded after format() or delete_tries().
+    /// Tries written or deleted through another connection leave it stale until this storage is
+    /// reopened, so it is only fit for metrics; anything that decides what to write must use
+    /// num_blocks().
+    pub fn num_blocks_cached(&mut self) -> usize {
+        let result = if self.last_extended.is_some() {
+            1
+        } else {
+            0
+        };
+        let count = match self.block_count_cache {
+            Some(count) => count,
+            None => {
+                let count = trie_sql::count_blocks(&self.db)
+                    .expect("Corruption: SQL Error on a non-fallible query.");
+                self.block_count_cache = Some(count);
+                count
+            }
+        };
+        result + (count as usize)
+    }
 }
@@ -32,7 +32,7 @@ use std::io::{
 use std::char::from_digit;
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
-use std::collections::{VecDeque, HashMap, HashSet};
+use std::collections::{VecDeque, HashMap, HashSet, BTreeMap};
 
 use std::fs;
 use std::path::{
@@ -57,6 +57,7 @@ use chainstate::stacks::index::{
 
 use chainstate::stacks::index::storage::{
     TrieFileStorage,
+    TrieSqlHashMapCursor,
 };
 
 use chainstate::stacks::index::bits::{
@@ -67,6 +68,8 @@ use chainstate::stacks::index::bits::{
     read_node_hash_bytes as bits_read_node_hash_bytes,
     read_nodetype,
     get_node_hash,
+    get_leaf_hash,
+    get_nodetype_hash_bytes,
 };
 
 use chainstate::stacks::index::node::{
@@ -86,7 +89,8 @@ use chainstate::stacks::index::node::{
 };
 
 use rusqlite::{
-    Connection, OptionalExtension,
+    Connection, OpenFlags, OptionalExtension,
+    blob::Blob,
     types::{ FromSql,
              ToSql },
     NO_PARAMS,
@@ -101,6 +105,7 @@ use std::convert::{
 use chainstate::stacks::index::Error as Error;
 
 use util::log;
+use util::get_epoch_time_secs;
 
 static SQL_MARF_DATA_TABLE: &str = "
 CREATE TABLE IF NOT EXISTS marf_data (
@@ -124,22 +129,160 @@ static SQL_EXTENSION_LOCKS_TABLE: &str = "
 CREATE TABLE IF NOT EXISTS block_extension_locks (block_hash TEXT PRIMARY KEY);
 ";
 
+/// Version 2 timestamps extension locks, so a lock stranded by a crashed process can be reaped.
+static SQL_EXTENSION_LOCKS_TIMESTAMP: &str = "
+ALTER TABLE block_extension_locks ADD COLUMN locked_at INTEGER NOT NULL DEFAULT 0;
+";
+
+/// Version 3 records the height of each committed block, where the writer knows it.  Rows written
+/// before the upgrade are left with a NULL height.
+static SQL_MARF_DATA_BLOCK_HEIGHT: &str = "
+ALTER TABLE marf_data ADD COLUMN block_height INTEGER;
+
+CREATE INDEX IF NOT EXISTS block_height_marf_data ON marf_data(block_height);
+";
+
+/// Version of the MARF database schema this binary reads and writes.  It is recorded in the
+/// database's `PRAGMA user_version`.  Databases created before versioning was introduced report
+/// version 0, and share the version 1 layout.
+pub const SQL_MARF_SCHEMA_VERSION: u32 = 3;
+
+/// How long an extension lock may be held before another writer may reap it and take over the
+/// block.  Extending a block never takes anywhere near this long, so an older lock was left
+/// behind by a process that died mid-extension.
+pub const BLOCK_EXTENSION_LOCK_TIMEOUT_SECS: u64 = 3600;
+
+pub fn get_schema_version(conn: &Connection) -> Result<u32, Error> {
+    let version = conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+    Ok(version)
+}
+
+/// Get the schema version, refusing a database written by a newer binary.
+fn check_schema_version(conn: &Connection) -> Result<u32, Error> {
+    let version = get_schema_version(conn)?;
+    if version > SQL_MARF_SCHEMA_VERSION {
+        error!("MARF database schema version {} is newer than the supported version {}", version, SQL_MARF_SCHEMA_VERSION);
+        return Err(Error::SchemaVersionError(version, SQL_MARF_SCHEMA_VERSION));
+    }
+    Ok(version)
+}
+
+/// Bring a database at schema version `from_version` up to SQL_MARF_SCHEMA_VERSION.
+fn migrate_tables(conn: &Connection, from_version: u32) -> Result<(), Error> {
+    if from_version == SQL_MARF_SCHEMA_VERSION {
+        return Ok(());
+    }
+    debug!("Migrate MARF database schema from version {} to {}", from_version, SQL_MARF_SCHEMA_VERSION);
+    if from_version < 2 {
+        // locks taken before the upgrade get locked_at = 0, so they are reapable right away
+        conn.execute_batch(SQL_EXTENSION_LOCKS_TIMESTAMP)?;
+    }
+    if from_version < 3 {
+        conn.execute_batch(SQL_MARF_DATA_BLOCK_HEIGHT)?;
+    }
+    conn.execute_batch(&format!("PRAGMA user_version = {};", SQL_MARF_SCHEMA_VERSION))?;
+    Ok(())
+}
+
+/// Instantiate the MARF tables, refusing to open a database written by a newer binary and
+/// migrating one written by an older binary.
 pub fn create_tables_if_needed(conn: &mut Connection) -> Result<(), Error> {
     let tx = conn.transaction()?;
 
+    let version = check_schema_version(&tx)?;
+
     tx.execute_batch(SQL_MARF_DATA_TABLE)?;
     tx.execute_batch(SQL_MARF_MINED_TABLE)?;
     tx.execute_batch(SQL_EXTENSION_LOCKS_TABLE)?;
 
+    migrate_tables(&tx, version)?;
+
     tx.commit().map_err(|e| e.into())
 }
 
+/// Open (creating if need be) the MARF database at `path` for reading and writing, and instantiate
+/// its tables.
+///
+/// The database runs in WAL mode, so that readers in other processes (such as those using
+/// open_readonly()) are not blocked while a block's trie is being committed, and the writer is not
+/// blocked by them.  `synchronous` stays at its default (FULL), so a committed trie survives power
+/// loss.
+pub fn open(path: &str) -> Result<Connection, Error> {
+    let mut conn = Connection::open(path)?;
+
+    // journal_mode reports the mode it ended up in, so it can't go through execute()
+    let journal_mode: String = conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |row| row.get(0))?;
+    if journal_mode.to_lowercase() != "wal" {
+        // e.g. in-memory databases, which have no journal file
+        debug!("MARF database {} is in journal mode {}, not WAL", path, journal_mode);
+    }
+
+    create_tables_if_needed(&mut conn)?;
+    Ok(conn)
+}
+
+/// Open an existing MARF database without write access, for tools that only read trie blobs.
+/// This neither creates tables nor changes the journal mode, and takes no locks beyond what
+/// SQLite needs to read.  Like open(), it refuses a database written by a newer binary.
+pub fn open_readonly(path: &str) -> Result<Connection, Error> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    check_schema_version(&conn)?;
+    Ok(conn)
+}
+
+/// Open a fresh in-memory MARF database with all tables instantiated, so tests can exercise the
+/// storage functions (including blob I/O) without a backing file.
+#[cfg(test)]
+pub fn open_memory_db() -> Result<Connection, Error> {
+    let mut conn = Connection::open(":memory:")?;
+    create_tables_if_needed(&mut conn)?;
+    Ok(conn)
+}
+
 pub fn get_block_identifier(conn: &Connection, bhh: &BlockHeaderHash) -> Result<u32, Error> {
     conn.query_row("SELECT block_id FROM marf_data WHERE block_hash = ?", &[bhh],
                    |row| row.get("block_id"))
         .map_err(|e| e.into())
 }
 
+/// SQLite's default cap on the number of bound parameters in one statement.
+const SQLITE_MAX_VARIABLE_NUMBER: usize = 999;
+
+/// Look up the identifiers of many committed blocks at once.  Hashes that are not in marf_data
+/// are left out of the returned map, rather than producing an error.
+pub fn get_block_identifiers(conn: &Connection, bhhs: &[BlockHeaderHash]) -> Result<HashMap<BlockHeaderHash, u32>, Error> {
+    let mut ids = HashMap::with_capacity(bhhs.len());
+    for chunk in bhhs.chunks(SQLITE_MAX_VARIABLE_NUMBER) {
+        let placeholders = vec!["?"; chunk.len()].join(", ");
+        let sql = format!("SELECT block_id, block_hash FROM marf_data WHERE block_hash IN ({})", placeholders);
+        let args: Vec<&dyn ToSql> = chunk.iter().map(|bhh| bhh as &dyn ToSql).collect();
+
+        let mut s = conn.prepare(&sql)?;
+        let rows = s.query_map(&args, |row| {
+            let block_id: u32 = row.get("block_id");
+            let block_hash: BlockHeaderHash = row.get("block_hash");
+            (block_hash, block_id)
+        })?;
+        for row in rows {
+            let (block_hash, block_id) = row?;
+            ids.insert(block_hash, block_id);
+        }
+    }
+    Ok(ids)
+}
+
+/// List the identifiers of the committed blocks with heights in `[lo, hi]`, ordered by height,
+/// from every fork.  Blocks written without a height (including every block committed before the
+/// block_height column was added) are never returned.
+pub fn get_block_identifiers_in_height_range(conn: &Connection, lo: u64, hi: u64) -> Result<Vec<u32>, Error> {
+    let mut s = conn.prepare("SELECT block_id FROM marf_data WHERE block_height >= ? AND block_height <= ? ORDER BY block_height, block_id")?;
+    let rows = s.query_map(&[lo as i64, hi as i64], |row| {
+        let block_id: u32 = row.get("block_id");
+        block_id
+    })?;
+    rows.map(|row| row.map_err(|e| e.into())).collect()
+}
+
 pub fn get_block_hash(conn: &Connection, local_id: u32) -> Result<BlockHeaderHash, Error> {
     let result = conn.query_row("SELECT block_hash FROM marf_data WHERE block_id = ?", &[local_id],
                                 |row| row.get("block_hash"))
@@ -150,15 +293,95 @@ pub fn get_block_hash(conn: &Connection, local_id: u32) -> Result<BlockHeaderHas
     })
 }
 
+/// Look up the hashes of many committed blocks at once -- the reverse of get_block_identifiers().
+/// Identifiers that are not in marf_data are left out of the returned map.
+pub fn get_block_hashes(conn: &Connection, local_ids: &[u32]) -> Result<HashMap<u32, BlockHeaderHash>, Error> {
+    let mut hashes = HashMap::with_capacity(local_ids.len());
+    for chunk in local_ids.chunks(SQLITE_MAX_VARIABLE_NUMBER) {
+        let placeholders = vec!["?"; chunk.len()].join(", ");
+        let sql = format!("SELECT block_id, block_hash FROM marf_data WHERE block_id IN ({})", placeholders);
+        let args: Vec<&dyn ToSql> = chunk.iter().map(|local_id| local_id as &dyn ToSql).collect();
+
+        let mut s = conn.prepare(&sql)?;
+        let rows = s.query_map(&args, |row| {
+            let block_id: u32 = row.get("block_id");
+            let block_hash: BlockHeaderHash = row.get("block_hash");
+            (block_id, block_hash)
+        })?;
+        for row in rows {
+            let (block_id, block_hash) = row?;
+            hashes.insert(block_id, block_hash);
+        }
+    }
+    Ok(hashes)
+}
+
+/// Log a failed trie read or write along with the block (and node, if any) it was working on, so
+/// that the log line alone identifies what failed.  The error is handed back unchanged, so callers
+/// can still match on it.
+fn log_error_context(e: Error, op: &str, block: &dyn fmt::Debug, ptr: Option<&TriePtr>) -> Error {
+    match ptr {
+        Some(ptr) => error!("{}: block {:?}, node {:?}: {}", op, block, ptr, e),
+        None => error!("{}: block {:?}: {}", op, block, e)
+    }
+    e
+}
+
 pub fn write_trie_blob(conn: &Connection, block_hash: &BlockHeaderHash, data: &[u8]) -> Result<u32, Error> {
-    let args: &[&dyn ToSql] = &[block_hash, &data];
-    let mut s = conn.prepare("INSERT INTO marf_data (block_hash, data) VALUES (?, ?)")?;
-    let block_id = s.insert(args)?
+    write_trie_blob_at_height(conn, block_hash, data, None)
+}
+
+/// Whether an insert failed because a row with the same unique key (for marf_data, the block
+/// hash) is already there.
+fn is_unique_violation(e: &SqliteError) -> bool {
+    match e {
+        SqliteError::SqliteFailure(ref failure, _) => failure.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE,
+        _ => false
+    }
+}
+
+/// Like write_trie_blob(), but also records the block's height, if known, so the blob can be
+/// found by get_block_identifiers_in_height_range().
+pub fn write_trie_blob_at_height(conn: &Connection, block_hash: &BlockHeaderHash, data: &[u8], block_height: Option<u64>) -> Result<u32, Error> {
+    let context = |e: rusqlite::Error| log_error_context(e.into(), "write_trie_blob", block_hash, None);
+    let block_height = block_height.map(|height| height as i64);
+    let args: &[&dyn ToSql] = &[block_hash, &data, &block_height];
+    let mut s = conn.prepare("INSERT INTO marf_data (block_hash, data, block_height) VALUES (?, ?, ?)").map_err(&context)?;
+    let block_id = s.insert(args)
+        .map_err(|e| match e {
+            ref e if is_unique_violation(e) => Error::BlockAlreadyExists(block_hash.clone()),
+            e => context(e)
+        })?
         .try_into()
         .expect("EXHAUSTION: MARF cannot track more than 2**31 - 1 blocks");
     Ok(block_id)
 }
 
+/// Insert a batch of trie blobs within a single transaction, returning their block IDs in input
+/// order.  If any insert fails (e.g. on a duplicate block hash), none of the batch is written.
+pub fn write_trie_blobs(conn: &mut Connection, blobs: &[(BlockHeaderHash, &[u8])]) -> Result<Vec<u32>, Error> {
+    let batch_context = |e: rusqlite::Error| log_error_context(e.into(), "write_trie_blobs", &format_args!("batch of {}", blobs.len()), None);
+    let tx = conn.transaction().map_err(&batch_context)?;
+    let block_ids = {
+        let mut s = tx.prepare("INSERT INTO marf_data (block_hash, data) VALUES (?, ?)").map_err(&batch_context)?;
+        let mut block_ids = Vec::with_capacity(blobs.len());
+        for (block_hash, data) in blobs.iter() {
+            let args: &[&dyn ToSql] = &[block_hash, data];
+            let block_id: u32 = s.insert(args)
+                .map_err(|e| match e {
+                    ref e if is_unique_violation(e) => Error::BlockAlreadyExists(block_hash.clone()),
+                    e => log_error_context(e.into(), "write_trie_blobs", block_hash, None)
+                })?
+                .try_into()
+                .expect("EXHAUSTION: MARF cannot track more than 2**31 - 1 blocks");
+            block_ids.push(block_id);
+        }
+        block_ids
+    };
+    tx.commit().map_err(&batch_context)?;
+    Ok(block_ids)
+}
+
 pub fn write_trie_blob_to_mined(conn: &Connection, block_hash: &BlockHeaderHash, data: &[u8]) -> Result<u32, Error> {
     let args: &[&dyn ToSql] = &[block_hash, &data];
     let mut s = conn.prepare("INSERT OR REPLACE INTO mined_blocks (block_hash, data) VALUES (?, ?)")?;
@@ -168,53 +391,121 @@ pub fn write_trie_blob_to_mined(conn: &Connection, block_hash: &BlockHeaderHash,
     Ok(block_id)
 }
 
+/// Read back the trie blob mined for `block_hash`.  Since write_trie_blob_to_mined() replaces
+/// any earlier blob for the same hash, only the most recently mined one is retained.
+pub fn get_mined_trie_blob(conn: &Connection, block_hash: &BlockHeaderHash) -> Result<Vec<u8>, Error> {
+    let blob = conn.query_row("SELECT data FROM mined_blocks WHERE block_hash = ?", &[block_hash],
+                              |row| row.get("data"))?;
+    Ok(blob)
+}
+
+/// List the hashes of the blocks in mined_blocks, in the order their latest blobs were written.
+pub fn list_mined_block_hashes(conn: &Connection) -> Result<Vec<BlockHeaderHash>, Error> {
+    let mut s = conn.prepare("SELECT block_hash FROM mined_blocks ORDER BY block_id")?;
+    let rows = s.query_map(NO_PARAMS, |row| {
+        let block_hash: BlockHeaderHash = row.get("block_hash");
+        block_hash
+    })?;
+    rows.map(|row| row.map_err(|e| e.into())).collect()
+}
+
 #[cfg(test)]
 pub fn read_all_block_hashes_and_roots(conn: &Connection) -> Result<Vec<(TrieHash, BlockHeaderHash)>, Error> {
-    let mut s = conn.prepare("SELECT block_hash, data FROM marf_data")?;
-    let rows = s.query_and_then(NO_PARAMS, |row| {
+    let mut ret = vec![];
+    for_each_block_root(conn, |block_hash, root_hash| {
+        ret.push((root_hash.clone(), block_hash.clone()));
+        true
+    })?;
+    Ok(ret)
+}
+
+/// Read the root hash of the trie in an open blob, without reading the rest of the blob.
+fn read_root_hash_from_blob(blob: &mut Blob) -> Result<TrieHash, Error> {
+    blob.seek(SeekFrom::Start(TrieFileStorage::root_ptr_disk() as u64))?;
+    Ok(TrieHash(read_hash_bytes(blob)?))
+}
+
+/// Visit the block hash and root hash of every committed trie, in the order the blocks were
+/// written.  Only the root hash is read from each blob, so this can walk a large MARF without
+/// holding it in memory.  Stops early if `visit` returns false.
+pub fn for_each_block_root<F>(conn: &Connection, mut visit: F) -> Result<(), Error>
+where F: FnMut(&BlockHeaderHash, &TrieHash) -> bool {
+    let mut s = conn.prepare("SELECT block_id, block_hash FROM marf_data ORDER BY block_id")?;
+    let rows = s.query_map(NO_PARAMS, |row| {
+        let block_id: u32 = row.get("block_id");
         let block_hash: BlockHeaderHash = row.get("block_hash");
-        let data = row.get_raw("data")
-            .as_blob().expect("DB Corruption: MARF data is non-blob");
-        let start = TrieFileStorage::root_ptr_disk() as usize;
-        let trie_hash = TrieHash(read_hash_bytes(&mut &data[start..])?);
-        Ok((trie_hash, block_hash))
+        (block_id, block_hash)
     })?;
-    rows.collect()
+
+    for row in rows {
+        let (block_id, block_hash) = row?;
+        let mut blob = open_trie_blob(conn, block_id)?;
+        let root_hash = read_root_hash_from_blob(&mut blob)
+            .map_err(|e| log_error_context(e, "for_each_block_root", &block_id, None))?;
+        if !visit(&block_hash, &root_hash) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Open a read-only handle on a committed trie blob.  A caller reading several nodes of the same
+/// trie can hold on to it and seek within it, instead of reopening the blob for every node.
+pub fn open_trie_blob(conn: &Connection, block_id: u32) -> Result<Blob, Error> {
+    let blob = conn.blob_open(rusqlite::DatabaseName::Main, "marf_data", "data", block_id.into(), true)?;
+    Ok(blob)
 }
 
 pub fn read_node_hash_bytes<W: Write>(conn: &Connection, w: &mut W, block_id: u32, ptr: &TriePtr) -> Result<(), Error> {
-    let mut blob = conn.blob_open(rusqlite::DatabaseName::Main, "marf_data", "data", block_id.into(), true)?;
-    let hash_buff = bits_read_node_hash_bytes(&mut blob, ptr)?;
+    let context = |e: Error| log_error_context(e, "read_node_hash_bytes", &block_id, Some(ptr));
+    let mut blob = open_trie_blob(conn, block_id).map_err(&context)?;
+    let hash_buff = bits_read_node_hash_bytes(&mut blob, ptr).map_err(&context)?;
     w.write_all(&hash_buff)
-        .map_err(|e| e.into())
+        .map_err(|e| context(e.into()))
 }
 
 pub fn read_node_hash_bytes_by_bhh<W: Write>(conn: &Connection, w: &mut W, bhh: &BlockHeaderHash, ptr: &TriePtr) -> Result<(), Error> {
-    let row_id: i64 = conn.query_row("SELECT block_id FROM marf_data WHERE block_hash = ?",
-                                     &[bhh], |r| r.get("block_id"))?;
-    let mut blob = conn.blob_open(rusqlite::DatabaseName::Main, "marf_data", "data", row_id, true)?;
-    let hash_buff = bits_read_node_hash_bytes(&mut blob, ptr)?;
-    w.write_all(&hash_buff)
-        .map_err(|e| e.into())
+    let block_id = get_block_identifier(conn, bhh)
+        .map_err(|e| log_error_context(e, "read_node_hash_bytes_by_bhh", bhh, Some(ptr)))?;
+    read_node_hash_bytes(conn, w, block_id, ptr)
+}
+
+/// Check that the node `ptr` points to starts within the blob, so that a corrupt pointer is
+/// reported as corruption rather than as a failed seek.  The node's ID and contents are checked
+/// as it is decoded.
+fn check_node_ptr(blob_len: usize, ptr: &TriePtr) -> Result<(), Error> {
+    let node_start = (ptr.ptr() as usize) + TRIEHASH_ENCODED_SIZE + 1;
+    if node_start > blob_len {
+        return Err(Error::CorruptionError(format!("read_node_type: node at {} is past the end of the {}-byte trie",
+                                                  ptr.ptr(), blob_len)));
+    }
+    Ok(())
 }
 
 pub fn read_node_type(conn: &Connection, block_id: u32, ptr: &TriePtr) -> Result<(TrieNodeType, TrieHash), Error> {
-    let mut blob = conn.blob_open(rusqlite::DatabaseName::Main, "marf_data", "data", block_id.into(), true)?;
-    read_nodetype(&mut blob, ptr)
+    let context = |e: Error| log_error_context(e, "read_node_type", &block_id, Some(ptr));
+    let mut blob = open_trie_blob(conn, block_id).map_err(&context)?;
+    read_node_type_from_blob(&mut blob, ptr).map_err(context)
+}
+
+/// Read a node from a handle obtained with open_trie_blob(), validating it the same way
+/// read_node_type() does.
+pub fn read_node_type_from_blob(blob: &mut Blob, ptr: &TriePtr) -> Result<(TrieNodeType, TrieHash), Error> {
+    check_node_ptr(blob.size() as usize, ptr)?;
+    read_nodetype(blob, ptr)
 }
 
 pub fn get_node_hash_bytes(conn: &Connection, block_id: u32, ptr: &TriePtr) -> Result<TrieHash, Error> {
-    let mut blob = conn.blob_open(rusqlite::DatabaseName::Main, "marf_data", "data", block_id.into(), true)?;
-    let hash_buff = bits_read_node_hash_bytes(&mut blob, ptr)?;
+    let context = |e: Error| log_error_context(e, "get_node_hash_bytes", &block_id, Some(ptr));
+    let mut blob = open_trie_blob(conn, block_id).map_err(&context)?;
+    let hash_buff = bits_read_node_hash_bytes(&mut blob, ptr).map_err(context)?;
     Ok(TrieHash(hash_buff))
 }
 
 pub fn get_node_hash_bytes_by_bhh(conn: &Connection, bhh: &BlockHeaderHash, ptr: &TriePtr) -> Result<TrieHash, Error> {
-    let row_id: i64 = conn.query_row("SELECT block_id FROM marf_data WHERE block_hash = ?",
-                                     &[bhh], |r| r.get("block_id"))?;
-    let mut blob = conn.blob_open(rusqlite::DatabaseName::Main, "marf_data", "data", row_id, true)?;
-    let hash_buff = bits_read_node_hash_bytes(&mut blob, ptr)?;
-    Ok(TrieHash(hash_buff))
+    let block_id = get_block_identifier(conn, bhh)
+        .map_err(|e| log_error_context(e, "get_node_hash_bytes_by_bhh", bhh, Some(ptr)))?;
+    get_node_hash_bytes(conn, block_id, ptr)
 }
 
 pub fn lock_bhh_for_extension(conn: &mut Connection, bhh: &BlockHeaderHash) -> Result<bool, Error> {
@@ -225,37 +516,885 @@ pub fn lock_bhh_for_extension(conn: &mut Connection, bhh: &BlockHeaderHash) -> R
         return Ok(false)
     }
 
-    let is_bhh_locked = tx.query_row("SELECT 1 FROM block_extension_locks WHERE block_hash = ? LIMIT 1", &[bhh],
-                                     |_row| ()).optional()?.is_some();
-    if is_bhh_locked {
-        return Ok(false)
+    let now = get_epoch_time_secs();
+    let locked_at: Option<i64> = tx.query_row("SELECT locked_at FROM block_extension_locks WHERE block_hash = ? LIMIT 1", &[bhh],
+                                              |row| row.get(0)).optional()?;
+    if let Some(locked_at) = locked_at {
+        if (locked_at as u64) + BLOCK_EXTENSION_LOCK_TIMEOUT_SECS > now {
+            return Ok(false)
+        }
+        warn!("Reaping stale extension lock on {} (locked at {})", bhh, locked_at);
+        tx.execute("DELETE FROM block_extension_locks WHERE block_hash = ?", &[bhh])?;
     }
 
-    tx.execute("INSERT INTO block_extension_locks (block_hash) VALUES (?)", &[bhh])?;
+    let args: &[&dyn ToSql] = &[bhh, &(now as i64)];
+    tx.execute("INSERT INTO block_extension_locks (block_hash, locked_at) VALUES (?, ?)", args)?;
 
     tx.commit()?;
     Ok(true)
 }
 
+/// Get the length in bytes of a single committed trie blob, without opening it.
+pub fn get_trie_blob_length(conn: &Connection, block_id: u32) -> Result<usize, Error> {
+    let data_len: i64 = conn.query_row("SELECT length(data) FROM marf_data WHERE block_id = ?", &[block_id],
+                                       |row| row.get(0))?;
+    Ok(data_len as usize)
+}
+
+/// Get the length in bytes of each committed trie blob, as (block_id, length).
+/// SQLite's length() reads the size out of the record header, so no blob data gets loaded.
+pub fn blob_size_histogram(conn: &Connection) -> Result<Vec<(u32, usize)>, Error> {
+    let mut s = conn.prepare("SELECT block_id, length(data) AS data_len FROM marf_data ORDER BY block_id")?;
+    let rows = s.query_map(NO_PARAMS, |row| {
+        let block_id: u32 = row.get("block_id");
+        let data_len: i64 = row.get("data_len");
+        (block_id, data_len as usize)
+    })?;
+    rows.map(|row| row.map_err(|e| e.into())).collect()
+}
+
+/// Bucket blob sizes into a power-of-two histogram.  Returns (bucket upper bound, count) pairs in
+/// ascending order, where a blob of length L lands in the bucket L.next_power_of_two().
+pub fn bucket_blob_sizes(sizes: &[(u32, usize)]) -> Vec<(usize, usize)> {
+    let mut buckets = BTreeMap::new();
+    for (_, len) in sizes.iter() {
+        *buckets.entry(len.next_power_of_two()).or_insert(0) += 1;
+    }
+    buckets.into_iter().collect()
+}
+
+/// List the committed blocks in marf_data that have no corresponding row in mined_blocks, in the
+/// order they were committed.  Useful for finding blocks whose mined trie was never persisted.
+pub fn mined_coverage(conn: &Connection) -> Result<Vec<BlockHeaderHash>, Error> {
+    let mut s = conn.prepare("SELECT block_hash FROM marf_data WHERE block_hash NOT IN (SELECT block_hash FROM mined_blocks) ORDER BY block_id")?;
+    let rows = s.query_map(NO_PARAMS, |row| {
+        let block_hash: BlockHeaderHash = row.get("block_hash");
+        block_hash
+    })?;
+    rows.map(|row| row.map_err(|e| e.into())).collect()
+}
+
+/// List the block hashes stored in both marf_data and mined_blocks.  This is expected while a
+/// mined block awaits its sortition, but a hash that stays in both tables after the block was
+/// committed indicates a stale mined trie that was never cleaned up.
+pub fn find_duplicate_hashes(conn: &Connection) -> Result<Vec<BlockHeaderHash>, Error> {
+    let mut s = conn.prepare("SELECT marf_data.block_hash AS block_hash FROM marf_data \
+                              INNER JOIN mined_blocks ON marf_data.block_hash = mined_blocks.block_hash \
+                              ORDER BY marf_data.block_id")?;
+    let rows = s.query_map(NO_PARAMS, |row| {
+        let block_hash: BlockHeaderHash = row.get("block_hash");
+        block_hash
+    })?;
+    rows.map(|row| row.map_err(|e| e.into())).collect()
+}
+
+/// Delete a committed trie blob from marf_data, e.g. one from a fork that lost.  Fails with
+/// NotFoundError if no trie is committed under `bhh`.  mined_blocks is left alone.
+///
+/// marf_data's block_id is not AUTOINCREMENT, so deleting the highest block_id lets the next
+/// insert reuse it.  Only delete tries that no kept trie back-points into (i.e. whole losing forks,
+/// descendants included), and reopen any TrieFileStorage on the database afterwards -- or delete
+/// through TrieFileStorage::delete_tries(), which resets that storage's caches.
+pub fn delete_trie_blob(conn: &Connection, bhh: &BlockHeaderHash) -> Result<(), Error> {
+    let deleted = conn.execute("DELETE FROM marf_data WHERE block_hash = ?", &[bhh])?;
+    if deleted == 0 {
+        return Err(Error::NotFoundError);
+    }
+    Ok(())
+}
+
+/// Delete several committed trie blobs within a single transaction.  If any of them is missing,
+/// none are deleted.  The caveats of delete_trie_blob() apply.
+pub fn delete_trie_blobs(conn: &mut Connection, bhhs: &[BlockHeaderHash]) -> Result<(), Error> {
+    let tx = conn.transaction()?;
+    for bhh in bhhs.iter() {
+        delete_trie_blob(&tx, bhh)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Check every committed trie in marf_data, and return the hashes of the blocks whose trie blob
+/// cannot be decoded or whose stored node hashes do not match their contents.  A trie's root hash
+/// also mixes in the root hashes of its ancestor tries, which cannot be recovered from marf_data
+/// alone, so the root is checked by walking down from it and recomputing the hash of every node
+/// beneath it.  Rows are visited one blob at a time, and a bad trie does not stop the pass.
+pub fn verify_trie_blobs(conn: &Connection) -> Result<Vec<BlockHeaderHash>, Error> {
+    let mut block_hash_cache = HashMap::new();
+    let mut corrupt = vec![];
+
+    let mut s = conn.prepare("SELECT block_id, block_hash FROM marf_data ORDER BY block_id")?;
+    let rows = s.query_map(NO_PARAMS, |row| {
+        let block_id: u32 = row.get("block_id");
+        let block_hash: BlockHeaderHash = row.get("block_hash");
+        (block_id, block_hash)
+    })?;
+
+    for row in rows {
+        let (block_id, block_hash) = row?;
+        let mut map = TrieSqlHashMapCursor::new(conn, &mut block_hash_cache);
+        if let Err(e) = verify_trie_blob(conn, block_id, &mut map) {
+            warn!("Trie for block {} (ID {}) failed verification: {:?}", &block_hash, block_id, &e);
+            corrupt.push(block_hash);
+        }
+    }
+
+    Ok(corrupt)
+}
+
+/// Walk one trie blob from its root, recomputing each node's hash from its children and
+/// comparing it to the hash stored alongside the node.
+fn verify_trie_blob<M: BlockMap>(conn: &Connection, block_id: u32, map: &mut M) -> Result<(), Error> {
+    let mut blob = open_trie_blob(conn, block_id)?;
+    let root_ptr = TriePtr::new(TrieNodeID::Node256 as u8, 0, TrieFileStorage::root_ptr_disk());
+
+    let mut visited = HashSet::new();
+    let mut frontier = vec![root_ptr];
+    while let Some(ptr) = frontier.pop() {
+        if !visited.insert(ptr.ptr()) {
+            return Err(Error::CorruptionError(format!("Node at {} is reachable more than once", ptr.ptr())));
+        }
+
+        let (node, stored_hash) = read_node_type_from_blob(&mut blob, &ptr)?;
+
+        let mut child_hashes = Vec::with_capacity(node.ptrs().len());
+        for child_ptr in node.ptrs().iter() {
+            if child_ptr.id() == TrieNodeID::Empty as u8 {
+                child_hashes.push(TrieHash::from_data(&[]));
+            }
+            else if !is_backptr(child_ptr.id()) {
+                child_hashes.push(TrieHash(bits_read_node_hash_bytes(&mut blob, child_ptr)?));
+                frontier.push(child_ptr.clone());
+            }
+            else {
+                // same as TrieFileStorage::write_children_hashes(): a back-pointer contributes the
+                // hash of the block it points to
+                let back_block_hash = map.get_block_hash_caching(child_ptr.back_block())?;
+                child_hashes.push(TrieHash(back_block_hash.0.clone()));
+            }
+        }
+
+        if ptr == root_ptr {
+            continue;
+        }
+
+        let computed_hash = match node {
+            TrieNodeType::Leaf(ref leaf) => get_leaf_hash(leaf),
+            _ => get_nodetype_hash_bytes(&node, &child_hashes, map)
+        };
+        if computed_hash != stored_hash {
+            return Err(Error::CorruptionError(format!("Node at {} has stored hash {:?}, but hashes to {:?}",
+                                                      ptr.ptr(), &stored_hash, &computed_hash)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy every committed trie from `src` into `dest`, creating `dest`'s tables if needed, and
+/// return how many marf_data rows were written.  Rows keep their block_id, since back-pointers
+/// inside the tries refer to other tries by it.  Extension locks are never copied, and mined
+/// tries are copied only if `include_mined` is set.  By default a block already in `dest` makes
+/// the copy fail on marf_data's UNIQUE constraint; with `ignore_existing`, a row already in `dest`
+/// with the same block_id and block_hash is skipped, while a row whose block_id or block_hash is
+/// taken in `dest` by a different block fails the copy with a CorruptionError.
+/// All rows are written in one transaction, and streamed from `src` one at a time.  `src` may be
+/// at an older schema version; it is only read, never migrated.
+pub fn copy_trie_blobs(src: &Connection, dest: &mut Connection, include_mined: bool, ignore_existing: bool) -> Result<u32, Error> {
+    create_tables_if_needed(dest)?;
+
+    // a database from before the block_height column was added has no heights to copy
+    let marf_data_src_columns =
+        if get_schema_version(src)? < 3 {
+            "block_id, block_hash, data, NULL"
+        } else {
+            "block_id, block_hash, data, block_height"
+        };
+
+    let tx = dest.transaction()?;
+    let copied = copy_table_blobs(src, &tx, "marf_data", "block_id, block_hash, data, block_height", marf_data_src_columns, ignore_existing)?;
+    if include_mined {
+        copy_table_blobs(src, &tx, "mined_blocks", "block_id, block_hash, data", "block_id, block_hash, data", ignore_existing)?;
+    }
+    tx.commit()?;
+
+    Ok(copied)
+}
+
+/// Copy `columns` of every row of `table`, the first three of which must be block_id,
+/// block_hash and data.  They are read from `src` as `src_columns`, which lists as many
+/// expressions, so a column `src` lacks can be filled in with e.g. NULL.
+fn copy_table_blobs(src: &Connection, dest: &Connection, table: &str, columns: &str, src_columns: &str, ignore_existing: bool) -> Result<u32, Error> {
+    let num_columns = columns.split(',').count();
+    let insert_sql = format!("INSERT INTO {} ({}) VALUES ({})", table, columns, vec!["?"; num_columns].join(", "));
+    let mut insert = dest.prepare(&insert_sql)?;
+    let mut existing = dest.prepare(&format!("SELECT block_id, block_hash FROM {} WHERE block_id = ? OR block_hash = ?", table))?;
+
+    let mut s = src.prepare(&format!("SELECT {} FROM {} ORDER BY block_id", src_columns, table))?;
+    let mut rows = s.query(NO_PARAMS)?;
+    let mut copied = 0;
+    while let Some(row_res) = rows.next() {
+        let row = row_res?;
+        let block_id: u32 = row.get("block_id");
+        let block_hash: BlockHeaderHash = row.get("block_hash");
+        let data = row.get_raw("data")
+            .as_blob().expect("DB Corruption: MARF data is non-blob");
+        let extra: Vec<rusqlite::types::Value> = (3..num_columns).map(|i| row.get(i)).collect();
+
+        if ignore_existing {
+            let args: &[&dyn ToSql] = &[&block_id, &block_hash];
+            let dest_rows = existing.query_map(args, |dest_row| {
+                let dest_block_id: u32 = dest_row.get("block_id");
+                let dest_block_hash: BlockHeaderHash = dest_row.get("block_hash");
+                (dest_block_id, dest_block_hash)
+            })?;
+            let mut already_copied = false;
+            for dest_row in dest_rows {
+                let (dest_block_id, dest_block_hash) = dest_row?;
+                if dest_block_id != block_id || dest_block_hash != block_hash {
+                    error!("Cannot copy {} {} ({}): {} {} is already there", table, block_id, &block_hash, dest_block_id, &dest_block_hash);
+                    return Err(Error::CorruptionError(format!("{} row {} ({}) conflicts with {} ({}) in the destination",
+                                                              table, block_id, &block_hash, dest_block_id, &dest_block_hash)));
+                }
+                already_copied = true;
+            }
+            if already_copied {
+                continue;
+            }
+        }
+
+        let mut args: Vec<&dyn ToSql> = vec![&block_id, &block_hash, &data];
+        args.extend(extra.iter().map(|value| value as &dyn ToSql));
+        copied += insert.execute(&args)? as u32;
+    }
+    Ok(copied)
+}
+
+/// On-disk size of the MARF's SQLite store, as reported by get_storage_stats().
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarfStorageStats {
+    pub marf_data_rows: u64,
+    pub marf_data_bytes: u64,
+    pub mined_blocks_rows: u64,
+    pub mined_blocks_bytes: u64,
+    /// page_count * page_size of the whole database file, including free pages and indexes
+    pub file_bytes: u64,
+}
+
+/// Gather row counts and blob byte totals for marf_data and mined_blocks, plus the database's
+/// total size.  Like blob_size_histogram(), this only reads blob lengths, never blob contents.
+pub fn get_storage_stats(conn: &Connection) -> Result<MarfStorageStats, Error> {
+    let (marf_data_rows, marf_data_bytes, mined_blocks_rows, mined_blocks_bytes): (i64, i64, i64, i64) =
+        conn.query_row("SELECT \
+                          (SELECT COUNT(*) FROM marf_data), \
+                          (SELECT IFNULL(SUM(length(data)), 0) FROM marf_data), \
+                          (SELECT COUNT(*) FROM mined_blocks), \
+                          (SELECT IFNULL(SUM(length(data)), 0) FROM mined_blocks)", NO_PARAMS,
+                       |row| (row.get(0), row.get(1), row.get(2), row.get(3)))?;
+    let page_count: i64 = conn.query_row("PRAGMA page_count", NO_PARAMS, |row| row.get(0))?;
+    let page_size: i64 = conn.query_row("PRAGMA page_size", NO_PARAMS, |row| row.get(0))?;
+
+    Ok(MarfStorageStats {
+        marf_data_rows: marf_data_rows as u64,
+        marf_data_bytes: marf_data_bytes as u64,
+        mined_blocks_rows: mined_blocks_rows as u64,
+        mined_blocks_bytes: mined_blocks_bytes as u64,
+        file_bytes: (page_count as u64) * (page_size as u64),
+    })
+}
+
+/// Number of pages in the database file that are unused, e.g. after tries were deleted.  Each is
+/// get_storage_stats()'s file_bytes / page count in size; compact() gives them back to the OS.
+pub fn get_free_page_count(conn: &Connection) -> Result<u64, Error> {
+    let free_pages: i64 = conn.query_row("PRAGMA freelist_count", NO_PARAMS, |row| row.get(0))?;
+    Ok(free_pages as u64)
+}
+
+/// Shrink the database file by releasing its free pages, e.g. after pruning forks with
+/// delete_trie_blobs().  Under `auto_vacuum = INCREMENTAL` this runs `PRAGMA incremental_vacuum`,
+/// which frees the pages in place.  Otherwise it runs `VACUUM`, which rewrites the whole database:
+/// that needs temporary disk space about the size of the database, blocks every other writer
+/// until it is done, and cannot run inside a transaction -- so calling this while one is open on
+/// `conn` fails with InProgressError.
+pub fn compact(conn: &Connection) -> Result<(), Error> {
+    if !conn.is_autocommit() {
+        error!("Cannot compact the MARF database while a transaction is open");
+        return Err(Error::InProgressError);
+    }
+
+    let auto_vacuum: i64 = conn.query_row("PRAGMA auto_vacuum", NO_PARAMS, |row| row.get(0))?;
+    if auto_vacuum == 2 {
+        conn.execute_batch("PRAGMA incremental_vacuum;")?;
+    }
+    else {
+        conn.execute_batch("VACUUM;")?;
+    }
+    Ok(())
+}
+
+/// The highest block_id in marf_data.  This is not a count of live rows: after deletions, some
+/// IDs at or below it no longer name a trie.
 pub fn count_blocks(conn: &Connection) -> Result<u32, Error> {
     let result = conn.query_row("SELECT IFNULL(MAX(block_id), 0) AS count FROM marf_data", NO_PARAMS, |row| row.get("count"))?;
     Ok(result)
 }
 
+/// The most recently written block -- its identifier, hash, and root hash -- or None if no block
+/// has been committed yet.  Only the root hash is read from the block's blob.
+pub fn get_latest_block(conn: &Connection) -> Result<Option<(u32, BlockHeaderHash, TrieHash)>, Error> {
+    let latest = conn.query_row("SELECT block_id, block_hash FROM marf_data ORDER BY block_id DESC LIMIT 1", NO_PARAMS, |row| {
+        let block_id: u32 = row.get("block_id");
+        let block_hash: BlockHeaderHash = row.get("block_hash");
+        (block_id, block_hash)
+    }).optional()?;
+
+    match latest {
+        Some((block_id, block_hash)) => {
+            let mut blob = open_trie_blob(conn, block_id)?;
+            let root_hash = read_root_hash_from_blob(&mut blob)
+                .map_err(|e| log_error_context(e, "get_latest_block", &block_id, None))?;
+            Ok(Some((block_id, block_hash, root_hash)))
+        },
+        None => Ok(None)
+    }
+}
+
 pub fn drop_lock(conn: &Connection, bhh: &BlockHeaderHash) -> Result<(), Error> {
     conn.execute("DELETE FROM block_extension_locks WHERE block_hash = ?", &[bhh])?;
     Ok(())
 }
 
+/// Delete every extension lock taken more than `max_age_secs` seconds ago, returning how many
+/// were removed.
+pub fn reap_stale_locks(conn: &Connection, max_age_secs: u64) -> Result<usize, Error> {
+    let cutoff = get_epoch_time_secs().saturating_sub(max_age_secs);
+    let reaped = conn.execute("DELETE FROM block_extension_locks WHERE locked_at < ?", &[cutoff as i64])?;
+    if reaped > 0 {
+        warn!("Reaped {} stale extension lock(s) older than {} seconds", reaped, max_age_secs);
+    }
+    Ok(reaped)
+}
+
 pub fn clear_lock_data(conn: &Connection) -> Result<(), Error> {
     conn.execute("DELETE FROM block_extension_locks", NO_PARAMS)?;
     Ok(())
 }
 
 pub fn clear_tables(conn: &mut Connection) -> Result<(), Error> {
+    clear_tables_inner(conn, true)
+}
+
+/// Like clear_tables(), but keeps the locally-mined blocks in mined_blocks, so that a chainstate
+/// reset does not throw away the miner's work.
+pub fn clear_committed_tables(conn: &mut Connection) -> Result<(), Error> {
+    clear_tables_inner(conn, false)
+}
+
+fn clear_tables_inner(conn: &mut Connection, clear_mined: bool) -> Result<(), Error> {
     let tx = conn.transaction()?;
     tx.execute("DELETE FROM block_extension_locks", NO_PARAMS)?;
     tx.execute("DELETE FROM marf_data", NO_PARAMS)?;
-    tx.execute("DELETE FROM mined_blocks", NO_PARAMS)?;
+    if clear_mined {
+        tx.execute("DELETE FROM mined_blocks", NO_PARAMS)?;
+    }
     tx.commit().map_err(|e| e.into())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn open_file_db(path: &str) -> Connection {
+        for file in [path.to_string(), format!("{}-wal", path), format!("{}-shm", path)].iter() {
+            match fs::metadata(file) {
+                Ok(_) => {
+                    fs::remove_file(file).unwrap();
+                },
+                Err(_) => {}
+            };
+        }
+        open(path).unwrap()
+    }
+
+    #[test]
+    fn copy_trie_blobs_between_dbs() {
+        let mut src = open_memory_db().unwrap();
+        for i in 1..4u8 {
+            write_trie_blob(&src, &BlockHeaderHash([i; 32]), &[i; 64]).unwrap();
+        }
+        write_trie_blob_to_mined(&src, &BlockHeaderHash([9u8; 32]), &[9u8; 64]).unwrap();
+        assert!(lock_bhh_for_extension(&mut src, &BlockHeaderHash([8u8; 32])).unwrap());
+
+        let mut dest = Connection::open(":memory:").unwrap();
+        assert_eq!(copy_trie_blobs(&src, &mut dest, false, false).unwrap(), 3);
+        for i in 1..4u8 {
+            let bhh = BlockHeaderHash([i; 32]);
+            assert_eq!(get_block_identifier(&dest, &bhh).unwrap(), get_block_identifier(&src, &bhh).unwrap());
+        }
+        assert_eq!(get_storage_stats(&dest).unwrap().mined_blocks_rows, 0);
+        let num_locks: i64 = dest.query_row("SELECT COUNT(*) FROM block_extension_locks", NO_PARAMS, |row| row.get(0)).unwrap();
+        assert_eq!(num_locks, 0);
+
+        // copying again collides, unless existing rows are skipped
+        assert!(copy_trie_blobs(&src, &mut dest, false, false).is_err());
+        write_trie_blob(&src, &BlockHeaderHash([4u8; 32]), &[4u8; 64]).unwrap();
+        assert_eq!(copy_trie_blobs(&src, &mut dest, true, true).unwrap(), 1);
+        assert_eq!(get_storage_stats(&dest).unwrap().mined_blocks_rows, 1);
+    }
+
+    #[test]
+    fn copy_trie_blobs_rejects_conflicting_rows() {
+        let src = open_memory_db().unwrap();
+        for i in 1..3u8 {
+            write_trie_blob(&src, &BlockHeaderHash([i; 32]), &[i; 64]).unwrap();
+        }
+
+        // dest has block_id 1 under a different hash
+        let mut dest = open_memory_db().unwrap();
+        write_trie_blob(&dest, &BlockHeaderHash([7u8; 32]), &[7u8; 64]).unwrap();
+        match copy_trie_blobs(&src, &mut dest, false, true) {
+            Err(Error::CorruptionError(_)) => {},
+            x => panic!("Expected a corruption error, got {:?}", x)
+        }
+        assert_eq!(get_storage_stats(&dest).unwrap().marf_data_rows, 1);
+
+        // dest has the first block's hash under a different block_id
+        let mut dest = open_memory_db().unwrap();
+        write_trie_blob(&dest, &BlockHeaderHash([7u8; 32]), &[7u8; 64]).unwrap();
+        write_trie_blob(&dest, &BlockHeaderHash([1u8; 32]), &[1u8; 64]).unwrap();
+        dest.execute("DELETE FROM marf_data WHERE block_id = 1", NO_PARAMS).unwrap();
+        match copy_trie_blobs(&src, &mut dest, false, true) {
+            Err(Error::CorruptionError(_)) => {},
+            x => panic!("Expected a corruption error, got {:?}", x)
+        }
+
+        // identical rows are skipped
+        let mut dest = open_memory_db().unwrap();
+        write_trie_blob(&dest, &BlockHeaderHash([1u8; 32]), &[1u8; 64]).unwrap();
+        assert_eq!(copy_trie_blobs(&src, &mut dest, false, true).unwrap(), 1);
+        assert_eq!(get_block_identifier(&dest, &BlockHeaderHash([2u8; 32])).unwrap(), 2);
+    }
+
+    #[test]
+    fn open_wal_and_readonly() {
+        let path = "/tmp/rust_marf_open_wal_and_readonly.sqlite";
+        let conn = open_file_db(path);
+        let journal_mode: String = conn.query_row("PRAGMA journal_mode", NO_PARAMS, |row| row.get(0)).unwrap();
+        assert_eq!(journal_mode.to_lowercase(), "wal");
+
+        let bhh = BlockHeaderHash([1u8; 32]);
+        let block_id = write_trie_blob(&conn, &bhh, &[0u8; 64]).unwrap();
+
+        let ro_conn = open_readonly(path).unwrap();
+        assert_eq!(get_block_identifier(&ro_conn, &bhh).unwrap(), block_id);
+        assert!(write_trie_blob(&ro_conn, &BlockHeaderHash([2u8; 32]), &[0u8; 64]).is_err());
+
+        assert!(open_readonly("/tmp/rust_marf_open_readonly_does_not_exist.sqlite").is_err());
+
+        // a database from a newer binary is refused here too
+        conn.execute_batch(&format!("PRAGMA user_version = {};", SQL_MARF_SCHEMA_VERSION + 1)).unwrap();
+        match open_readonly(path) {
+            Err(Error::SchemaVersionError(found, supported)) => {
+                assert_eq!(found, SQL_MARF_SCHEMA_VERSION + 1);
+                assert_eq!(supported, SQL_MARF_SCHEMA_VERSION);
+            },
+            x => panic!("Expected a schema version error, got {:?}", x)
+        }
+    }
+
+    #[test]
+    fn write_trie_blobs_batch() {
+        let num_blobs = 1000;
+        let data = vec![0u8; 64];
+        let blobs: Vec<(BlockHeaderHash, &[u8])> = (0..num_blobs)
+            .map(|i: u32| {
+                let mut bytes = [0u8; 32];
+                bytes[0..4].copy_from_slice(&i.to_be_bytes());
+                (BlockHeaderHash(bytes), &data[..])
+            })
+            .collect();
+
+        let mut conn = open_file_db("/tmp/rust_trie_sql_write_trie_blobs_batch");
+        let block_ids = write_trie_blobs(&mut conn, &blobs).unwrap();
+
+        assert_eq!(block_ids.len(), blobs.len());
+        for ((block_hash, _), block_id) in blobs.iter().zip(block_ids.iter()) {
+            assert_eq!(get_block_identifier(&conn, block_hash).unwrap(), *block_id);
+        }
+
+        // a duplicate block hash fails the whole batch
+        let new_hash = BlockHeaderHash([0xffu8; 32]);
+        let dup_batch = vec![(new_hash.clone(), &data[..]), (blobs[0].0.clone(), &data[..])];
+        assert!(write_trie_blobs(&mut conn, &dup_batch).is_err());
+        assert!(get_block_identifier(&conn, &new_hash).is_err());
+        assert_eq!(count_blocks(&conn).unwrap(), num_blobs);
+    }
+
+    #[test]
+    fn duplicate_block_hash_is_typed_error() {
+        let conn = open_memory_db().unwrap();
+        let bhh = BlockHeaderHash([1u8; 32]);
+        let block_id = write_trie_blob(&conn, &bhh, &[1u8; 64]).unwrap();
+
+        match write_trie_blob(&conn, &bhh, &[2u8; 64]) {
+            Err(Error::BlockAlreadyExists(dup)) => assert_eq!(dup, bhh),
+            x => panic!("Expected BlockAlreadyExists, got {:?}", x)
+        }
+        assert_eq!(get_block_identifier(&conn, &bhh).unwrap(), block_id);
+        assert_eq!(count_blocks(&conn).unwrap(), block_id);
+    }
+
+    #[test]
+    fn memory_db_blob_io() {
+        let conn = open_memory_db().unwrap();
+        let bhh = BlockHeaderHash([1u8; 32]);
+
+        let mut data = vec![0u8; 64];
+        for i in 0..data.len() {
+            data[i] = i as u8;
+        }
+
+        let block_id = write_trie_blob(&conn, &bhh, &data).unwrap();
+        assert_eq!(get_block_identifier(&conn, &bhh).unwrap(), block_id);
+        assert_eq!(get_block_hash(&conn, block_id).unwrap(), bhh);
+        assert_eq!(get_trie_blob_length(&conn, block_id).unwrap(), data.len());
+        assert!(get_trie_blob_length(&conn, block_id + 1).is_err());
+
+        let ptr = TriePtr::new(TrieNodeID::Node256 as u8, 0, 16);
+        let hash = get_node_hash_bytes(&conn, block_id, &ptr).unwrap();
+        assert_eq!(hash.as_bytes().to_vec(), data[16..48].to_vec());
+
+        let hash_by_bhh = get_node_hash_bytes_by_bhh(&conn, &bhh, &ptr).unwrap();
+        assert_eq!(hash, hash_by_bhh);
+
+        let missing = BlockHeaderHash([2u8; 32]);
+        let ids = get_block_identifiers(&conn, &[bhh.clone(), missing.clone()]).unwrap();
+        assert_eq!(ids.len(), 1);
+        assert_eq!(ids.get(&bhh), Some(&block_id));
+        assert!(ids.get(&missing).is_none());
+
+        let hashes = get_block_hashes(&conn, &[block_id, block_id + 1]).unwrap();
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(hashes.get(&block_id), Some(&bhh));
+        assert!(hashes.get(&(block_id + 1)).is_none());
+
+        let stats = get_storage_stats(&conn).unwrap();
+        assert_eq!(stats.marf_data_rows, 1);
+        assert_eq!(stats.marf_data_bytes, data.len() as u64);
+        assert_eq!(stats.mined_blocks_rows, 0);
+        assert_eq!(stats.mined_blocks_bytes, 0);
+        assert!(stats.file_bytes > 0);
+
+        // one handle can serve several reads
+        let mut blob = open_trie_blob(&conn, block_id).unwrap();
+        for offset in [0u32, 8, 16].iter() {
+            let ptr = TriePtr::new(TrieNodeID::Node256 as u8, 0, *offset);
+            let hash = bits_read_node_hash_bytes(&mut blob, &ptr).unwrap();
+            assert_eq!(hash.to_vec(), data[(*offset as usize)..(*offset as usize + 32)].to_vec());
+        }
+        assert!(open_trie_blob(&conn, block_id + 1).is_err());
+    }
+
+    #[test]
+    fn compact_reclaims_free_pages() {
+        let mut conn = open_memory_db().unwrap();
+        let data = vec![0u8; 16384];
+        let bhhs: Vec<_> = (0..16u8).map(|i| BlockHeaderHash([i; 32])).collect();
+        for bhh in bhhs.iter() {
+            write_trie_blob(&conn, bhh, &data).unwrap();
+        }
+        assert_eq!(get_free_page_count(&conn).unwrap(), 0);
+
+        delete_trie_blobs(&mut conn, &bhhs).unwrap();
+        assert!(get_free_page_count(&conn).unwrap() > 0);
+
+        conn.execute_batch("BEGIN;").unwrap();
+        match compact(&conn) {
+            Err(Error::InProgressError) => {},
+            x => panic!("Expected InProgressError, got {:?}", x)
+        }
+        conn.execute_batch("ROLLBACK;").unwrap();
+
+        compact(&conn).unwrap();
+        assert_eq!(get_free_page_count(&conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn mined_blobs_keep_latest() {
+        let conn = open_memory_db().unwrap();
+        let bhh_1 = BlockHeaderHash([1u8; 32]);
+        let bhh_2 = BlockHeaderHash([2u8; 32]);
+
+        write_trie_blob_to_mined(&conn, &bhh_1, &[1u8; 16]).unwrap();
+        write_trie_blob_to_mined(&conn, &bhh_2, &[2u8; 16]).unwrap();
+        write_trie_blob_to_mined(&conn, &bhh_1, &[3u8; 16]).unwrap();
+
+        assert_eq!(get_mined_trie_blob(&conn, &bhh_1).unwrap(), vec![3u8; 16]);
+        assert_eq!(get_mined_trie_blob(&conn, &bhh_2).unwrap(), vec![2u8; 16]);
+        assert_eq!(list_mined_block_hashes(&conn).unwrap(), vec![bhh_2, bhh_1]);
+
+        match get_mined_trie_blob(&conn, &BlockHeaderHash([4u8; 32])) {
+            Err(Error::NotFoundError) => {},
+            x => panic!("Expected NotFoundError, got {:?}", x)
+        }
+    }
+
+    #[test]
+    fn clear_committed_tables_keeps_mined() {
+        let mut conn = open_memory_db().unwrap();
+        let bhh_1 = BlockHeaderHash([1u8; 32]);
+        let bhh_2 = BlockHeaderHash([2u8; 32]);
+        write_trie_blob(&conn, &bhh_1, &[1u8; 16]).unwrap();
+        write_trie_blob_to_mined(&conn, &bhh_2, &[2u8; 16]).unwrap();
+        assert!(lock_bhh_for_extension(&mut conn, &bhh_2).unwrap());
+
+        clear_committed_tables(&mut conn).unwrap();
+        assert!(get_block_identifier(&conn, &bhh_1).is_err());
+        assert!(lock_bhh_for_extension(&mut conn, &bhh_2).unwrap());
+        assert_eq!(get_mined_trie_blob(&conn, &bhh_2).unwrap(), vec![2u8; 16]);
+
+        clear_tables(&mut conn).unwrap();
+        assert!(list_mined_block_hashes(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn delete_trie_blobs_by_hash() {
+        let mut conn = open_memory_db().unwrap();
+        let data = vec![0u8; 64];
+        let bhhs: Vec<_> = (0..4u8).map(|i| BlockHeaderHash([i; 32])).collect();
+        for bhh in bhhs.iter() {
+            write_trie_blob(&conn, bhh, &data).unwrap();
+        }
+        write_trie_blob_to_mined(&conn, &bhhs[1], &data).unwrap();
+
+        delete_trie_blob(&conn, &bhhs[1]).unwrap();
+        assert!(get_block_identifier(&conn, &bhhs[1]).is_err());
+        match delete_trie_blob(&conn, &bhhs[1]) {
+            Err(Error::NotFoundError) => {},
+            x => panic!("Expected NotFoundError, got {:?}", x)
+        }
+
+        // mined_blocks is untouched
+        let mined: u32 = conn.query_row("SELECT COUNT(*) FROM mined_blocks", NO_PARAMS, |row| row.get(0)).unwrap();
+        assert_eq!(mined, 1);
+
+        // a missing hash fails the whole batch
+        assert!(delete_trie_blobs(&mut conn, &[bhhs[2].clone(), bhhs[1].clone()]).is_err());
+        assert!(get_block_identifier(&conn, &bhhs[2]).is_ok());
+
+        delete_trie_blobs(&mut conn, &[bhhs[2].clone(), bhhs[3].clone()]).unwrap();
+        assert_eq!(count_blocks(&conn).unwrap(), 1);
+    }
+
+    #[test]
+    fn schema_version_guard() {
+        let mut conn = open_memory_db().unwrap();
+        assert_eq!(get_schema_version(&conn).unwrap(), SQL_MARF_SCHEMA_VERSION);
+
+        // re-opening at the current version is a no-op
+        create_tables_if_needed(&mut conn).unwrap();
+        assert_eq!(get_schema_version(&conn).unwrap(), SQL_MARF_SCHEMA_VERSION);
+
+        // a database from a newer binary is refused
+        conn.execute_batch(&format!("PRAGMA user_version = {};", SQL_MARF_SCHEMA_VERSION + 1)).unwrap();
+        match create_tables_if_needed(&mut conn) {
+            Err(Error::SchemaVersionError(found, supported)) => {
+                assert_eq!(found, SQL_MARF_SCHEMA_VERSION + 1);
+                assert_eq!(supported, SQL_MARF_SCHEMA_VERSION);
+            },
+            x => panic!("Expected a schema version error, got {:?}", x)
+        }
+    }
+
+    #[test]
+    fn stale_extension_locks_are_reaped() {
+        let mut conn = open_memory_db().unwrap();
+        let bhh = BlockHeaderHash([1u8; 32]);
+        let stranded = BlockHeaderHash([2u8; 32]);
+
+        assert!(lock_bhh_for_extension(&mut conn, &bhh).unwrap());
+        assert!(!lock_bhh_for_extension(&mut conn, &bhh).unwrap());
+
+        // a lock left behind by a crashed process long ago can be re-taken
+        let old_time = (get_epoch_time_secs() - BLOCK_EXTENSION_LOCK_TIMEOUT_SECS - 1) as i64;
+        let args: &[&dyn ToSql] = &[&stranded, &old_time];
+        conn.execute("INSERT INTO block_extension_locks (block_hash, locked_at) VALUES (?, ?)", args).unwrap();
+        assert!(lock_bhh_for_extension(&mut conn, &stranded).unwrap());
+        assert!(!lock_bhh_for_extension(&mut conn, &stranded).unwrap());
+
+        conn.execute("UPDATE block_extension_locks SET locked_at = ? WHERE block_hash = ?", args).unwrap();
+        assert_eq!(reap_stale_locks(&conn, BLOCK_EXTENSION_LOCK_TIMEOUT_SECS).unwrap(), 1);
+        assert_eq!(reap_stale_locks(&conn, BLOCK_EXTENSION_LOCK_TIMEOUT_SECS).unwrap(), 0);
+        assert!(!lock_bhh_for_extension(&mut conn, &bhh).unwrap());
+    }
+
+    #[test]
+    fn migrate_extension_locks_from_v1() {
+        let mut conn = Connection::open(":memory:").unwrap();
+        conn.execute_batch(SQL_MARF_DATA_TABLE).unwrap();
+        conn.execute_batch(SQL_MARF_MINED_TABLE).unwrap();
+        conn.execute_batch(SQL_EXTENSION_LOCKS_TABLE).unwrap();
+        conn.execute_batch("PRAGMA user_version = 1;").unwrap();
+        conn.execute("INSERT INTO block_extension_locks (block_hash) VALUES (?)", &[BlockHeaderHash([1u8; 32])]).unwrap();
+        let args: &[&dyn ToSql] = &[&BlockHeaderHash([2u8; 32]), &vec![0u8; 64]];
+        conn.execute("INSERT INTO marf_data (block_hash, data) VALUES (?, ?)", args).unwrap();
+
+        create_tables_if_needed(&mut conn).unwrap();
+        assert_eq!(get_schema_version(&conn).unwrap(), SQL_MARF_SCHEMA_VERSION);
+
+        // the pre-upgrade lock has no timestamp, so it is treated as stale
+        assert!(lock_bhh_for_extension(&mut conn, &BlockHeaderHash([1u8; 32])).unwrap());
+
+        // the pre-upgrade block has no height, so range queries skip it
+        assert_eq!(get_block_identifiers_in_height_range(&conn, 0, u32::max_value() as u64).unwrap(), vec![]);
+        let block_id = write_trie_blob_at_height(&conn, &BlockHeaderHash([3u8; 32]), &[0u8; 64], Some(1)).unwrap();
+        assert_eq!(get_block_identifiers_in_height_range(&conn, 0, 1).unwrap(), vec![block_id]);
+    }
+
+    #[test]
+    fn for_each_block_root_streams_roots() {
+        let conn = open_memory_db().unwrap();
+        assert_eq!(get_latest_block(&conn).unwrap(), None);
+
+        let root_start = TrieFileStorage::root_ptr_disk() as usize;
+        let mut expected = vec![];
+        for i in 0..3u8 {
+            let bhh = BlockHeaderHash([i; 32]);
+            let mut data = vec![0u8; root_start];
+            data.extend_from_slice(&[i + 10; 32]);
+            data.extend_from_slice(&[0xffu8; 16]);
+            write_trie_blob(&conn, &bhh, &data).unwrap();
+            expected.push((TrieHash([i + 10; 32]), bhh));
+        }
+
+        assert_eq!(read_all_block_hashes_and_roots(&conn).unwrap(), expected);
+        assert_eq!(get_latest_block(&conn).unwrap(), Some((3, expected[2].1.clone(), expected[2].0.clone())));
+
+        let mut visited = vec![];
+        for_each_block_root(&conn, |block_hash, _root_hash| {
+            visited.push(block_hash.clone());
+            visited.len() < 2
+        }).unwrap();
+        assert_eq!(visited, vec![expected[0].1.clone(), expected[1].1.clone()]);
+    }
+
+    #[test]
+    fn block_identifiers_in_height_range() {
+        let conn = open_memory_db().unwrap();
+        let data = vec![0u8; 64];
+        // two forks share heights 2 and 3
+        let heights = [1u64, 2, 3, 2, 3, 4];
+        let block_ids: Vec<_> = heights.iter().enumerate()
+            .map(|(i, height)| write_trie_blob_at_height(&conn, &BlockHeaderHash([i as u8; 32]), &data, Some(*height)).unwrap())
+            .collect();
+        write_trie_blob(&conn, &BlockHeaderHash([0xff; 32]), &data).unwrap();
+
+        assert_eq!(get_block_identifiers_in_height_range(&conn, 2, 3).unwrap(),
+                   vec![block_ids[1], block_ids[3], block_ids[2], block_ids[4]]);
+        assert_eq!(get_block_identifiers_in_height_range(&conn, 4, 10).unwrap(), vec![block_ids[5]]);
+        assert_eq!(get_block_identifiers_in_height_range(&conn, 5, 10).unwrap(), vec![]);
+
+        // heights survive a copy
+        let mut dest = Connection::open(":memory:").unwrap();
+        copy_trie_blobs(&conn, &mut dest, false, false).unwrap();
+        assert_eq!(get_block_identifiers_in_height_range(&dest, 2, 3).unwrap(),
+                   get_block_identifiers_in_height_range(&conn, 2, 3).unwrap());
+    }
+
+    #[test]
+    fn copy_from_unmigrated_source() {
+        let src = Connection::open(":memory:").unwrap();
+        src.execute_batch(SQL_MARF_DATA_TABLE).unwrap();
+        src.execute_batch(SQL_MARF_MINED_TABLE).unwrap();
+        src.execute_batch("PRAGMA user_version = 1;").unwrap();
+        let args: &[&dyn ToSql] = &[&BlockHeaderHash([1u8; 32]), &vec![1u8; 64]];
+        src.execute("INSERT INTO marf_data (block_hash, data) VALUES (?, ?)", args).unwrap();
+
+        // the source is read as-is, and its blocks arrive without heights
+        let mut dest = Connection::open(":memory:").unwrap();
+        assert_eq!(copy_trie_blobs(&src, &mut dest, true, false).unwrap(), 1);
+        assert_eq!(get_schema_version(&src).unwrap(), 1);
+        assert_eq!(get_block_identifier(&dest, &BlockHeaderHash([1u8; 32])).unwrap(), 1);
+        assert_eq!(get_block_identifiers_in_height_range(&dest, 0, u32::max_value() as u64).unwrap(), vec![]);
+    }
+
+    fn make_trie_blob(leaf_hash: TrieHash) -> Vec<u8> {
+        let start = TrieFileStorage::root_ptr_disk();
+        let leaf = TrieNodeType::Leaf(TrieLeaf::new(&vec![], &[7u8; 40].to_vec()));
+
+        let mut root = TrieNode256::new(&vec![]);
+        let leaf_start = start + get_node_byte_len(&TrieNodeType::Node256(root.clone())) as u32;
+        assert!(root.insert(&TriePtr::new(TrieNodeID::Leaf as u8, 0x01, leaf_start)));
+
+        let mut buffer = Cursor::new(vec![0u8; start as usize]);
+        buffer.seek(SeekFrom::Start(start as u64)).unwrap();
+        write_nodetype_bytes(&mut buffer, &TrieNodeType::Node256(root), TrieHash([1u8; 32])).unwrap();
+        write_nodetype_bytes(&mut buffer, &leaf, leaf_hash).unwrap();
+        buffer.into_inner()
+    }
+
+    #[test]
+    fn verify_trie_blobs_finds_corruption() {
+        let conn = open_memory_db().unwrap();
+        let leaf_hash = get_leaf_hash(&TrieLeaf::new(&vec![], &[7u8; 40].to_vec()));
+
+        write_trie_blob(&conn, &BlockHeaderHash([1u8; 32]), &make_trie_blob(leaf_hash)).unwrap();
+        assert_eq!(verify_trie_blobs(&conn).unwrap(), vec![]);
+
+        // a leaf whose stored hash is wrong, a truncated blob, then another good trie
+        write_trie_blob(&conn, &BlockHeaderHash([2u8; 32]), &make_trie_blob(TrieHash([9u8; 32]))).unwrap();
+        write_trie_blob(&conn, &BlockHeaderHash([3u8; 32]), &[0u8; 40]).unwrap();
+        write_trie_blob(&conn, &BlockHeaderHash([4u8; 32]), &make_trie_blob(leaf_hash)).unwrap();
+
+        assert_eq!(verify_trie_blobs(&conn).unwrap(), vec![BlockHeaderHash([2u8; 32]), BlockHeaderHash([3u8; 32])]);
+    }
+
+    #[test]
+    fn read_node_type_rejects_out_of_bounds_ptr() {
+        let conn = open_memory_db().unwrap();
+
+        let node = TrieNodeType::Node4(TrieNode4::new(&vec![1, 2, 3]));
+        let hash = TrieHash([3u8; 32]);
+        let start = TrieFileStorage::root_ptr_disk();
+
+        let mut buffer = Cursor::new(vec![0u8; start as usize]);
+        buffer.seek(SeekFrom::Start(start as u64)).unwrap();
+        write_nodetype_bytes(&mut buffer, &node, hash.clone()).unwrap();
+        let data = buffer.into_inner();
+
+        let ptr = TriePtr::new(TrieNodeID::Node4 as u8, 0, start);
+        let block_id = write_trie_blob(&conn, &BlockHeaderHash([4u8; 32]), &data).unwrap();
+        assert_eq!(read_node_type(&conn, block_id, &ptr).unwrap(), (node, hash));
+
+        // a pointer past the end of the blob is corruption, not a failed seek
+        let past_end_ptr = TriePtr::new(TrieNodeID::Node4 as u8, 0, data.len() as u32 + 1);
+        match read_node_type(&conn, block_id, &past_end_ptr) {
+            Err(Error::CorruptionError(_)) => {},
+            x => panic!("Expected a corruption error, got {:?}", x)
+        }
+
+        // a stored node ID that disagrees with the pointer's is caught by the node decoders
+        let leaf = TrieNodeType::Leaf(TrieLeaf::new(&vec![], &[7u8; 40].to_vec()));
+        let mut buffer = Cursor::new(vec![0u8; start as usize]);
+        buffer.seek(SeekFrom::Start(start as u64)).unwrap();
+        write_nodetype_bytes(&mut buffer, &leaf, hash.clone()).unwrap();
+        let leaf_data = buffer.into_inner();
+        let leaf_ptr = TriePtr::new(TrieNodeID::Leaf as u8, 0, start);
+
+        let mut corrupt_data = leaf_data.clone();
+        corrupt_data[start as usize + TRIEHASH_ENCODED_SIZE] = TrieNodeID::Node16 as u8;
+        let corrupt_block_id = write_trie_blob(&conn, &BlockHeaderHash([5u8; 32]), &corrupt_data).unwrap();
+        match read_node_type(&conn, corrupt_block_id, &leaf_ptr) {
+            Err(Error::CorruptionError(_)) => {},
+            x => panic!("Expected a corruption error, got {:?}", x)
+        }
+
+        let mut corrupt_data = data.clone();
+        corrupt_data[start as usize + TRIEHASH_ENCODED_SIZE] = TrieNodeID::Node16 as u8;
+        let corrupt_block_id = write_trie_blob(&conn, &BlockHeaderHash([6u8; 32]), &corrupt_data).unwrap();
+        match read_node_type(&conn, corrupt_block_id, &ptr) {
+            Err(Error::CorruptionError(_)) => {},
+            x => panic!("Expected a corruption error, got {:?}", x)
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::io::{BufReader, Read};
 use std::fs::File;
@@ -12,8 +13,9 @@ use stacks::net::connection::ConnectionOptions;
 use stacks::net::{Neighbor, NeighborKey, PeerAddress};
 use stacks::util::secp256k1::Secp256k1PublicKey;
 use stacks::util::hash::{to_hex, hex_bytes};
-use stacks::vm::types::{PrincipalData, QualifiedContractIdentifier, AssetIdentifier} ;
+use stacks::vm::types::{PrincipalData, StandardPrincipalData, QualifiedContractIdentifier, AssetIdentifier, Value} ;
 use stacks::vm::costs::ExecutionCost;
+use stacks::chainstate::stacks::TransactionPayload;
 
 use super::node::TESTNET_CHAIN_ID;
 use super::neon_node::TESTNET_PEER_VERSION;
@@ -183,6 +185,12 @@ pub const HELIUM_BLOCK_LIMIT: ExecutionCost = ExecutionCost {
     runtime: 1_00_000_000,
 };
 
+pub const DEFAULT_OBSERVER_RETRY_COUNT: u32 = 3;
+pub const DEFAULT_OBSERVER_RETRY_BASE_DELAY_MS: u64 = 100;
+pub const DEFAULT_OBSERVER_MAX_QUEUED_PAYLOADS: usize = 1000;
+pub const DEFAULT_OBSERVER_CONNECT_TIMEOUT_MS: u64 = 5_000;
+pub const DEFAULT_OBSERVER_WRITE_TIMEOUT_MS: u64 = 30_000;
+
 impl Config {
 
     pub fn from_config_file_path(path: &str) -> Config {
@@ -286,9 +294,45 @@ impl Config {
                         .map(|e| EventKeyType::from_string(e).unwrap())
                         .collect();
 
+                    let tx_outcome_filter = match observer.tx_outcome_filter {
+                        Some(raw_filter) => TxOutcomeFilter::from_string(&raw_filter)
+                            .expect("Setting events_observer.tx_outcome_filter should be one of: success, failure, all"),
+                        None => TxOutcomeFilter::All
+                    };
+
+                    let transport = match observer.transport {
+                        Some(raw_transport) => EventTransportType::from_string(&raw_transport)
+                            .expect("Setting events_observer.transport should be one of: http, file, stdout"),
+                        None => EventTransportType::Http
+                    };
+
+                    let tx_type_filter = observer.tx_type_filter.map(|raw_types| {
+                        raw_types.iter()
+                            .map(|t| TxType::from_string(t)
+                                 .expect("Setting events_observer.tx_type_filter entries should be one of: token-transfer, contract-call, smart-contract, poison-microblock, coinbase"))
+                            .collect()
+                    });
+
+                    if let Some(sample_rate) = observer.sample_rate {
+                        if sample_rate < 0.0 || sample_rate > 1.0 {
+                            panic!("Setting events_observer.sample_rate should be between 0.0 and 1.0");
+                        }
+                    }
+
                     observers.push(EventObserverConfig {
                         endpoint: observer.endpoint,
-                        events_keys
+                        events_keys,
+                        transport,
+                        tx_outcome_filter,
+                        tx_type_filter,
+                        sample_rate: observer.sample_rate,
+                        retry_count: observer.retry_count.unwrap_or(DEFAULT_OBSERVER_RETRY_COUNT),
+                        retry_base_delay_ms: observer.retry_base_delay_ms.unwrap_or(DEFAULT_OBSERVER_RETRY_BASE_DELAY_MS),
+                        max_queued_payloads: observer.max_queued_payloads.unwrap_or(DEFAULT_OBSERVER_MAX_QUEUED_PAYLOADS),
+                        connect_timeout_ms: observer.connect_timeout_ms.unwrap_or(DEFAULT_OBSERVER_CONNECT_TIMEOUT_MS),
+                        write_timeout_ms: observer.write_timeout_ms.unwrap_or(DEFAULT_OBSERVER_WRITE_TIMEOUT_MS),
+                        tls_skip_verify: observer.tls_skip_verify.unwrap_or(false),
+                        headers: observer.headers.unwrap_or_default(),
                     });
                 }
                 observers
@@ -302,6 +346,17 @@ impl Config {
                 events_observers.push(EventObserverConfig {
                     endpoint: val,
                     events_keys: vec![EventKeyType::AnyEvent],
+                    transport: EventTransportType::Http,
+                    tx_outcome_filter: TxOutcomeFilter::All,
+                    tx_type_filter: None,
+                    sample_rate: None,
+                    retry_count: DEFAULT_OBSERVER_RETRY_COUNT,
+                    retry_base_delay_ms: DEFAULT_OBSERVER_RETRY_BASE_DELAY_MS,
+                    max_queued_payloads: DEFAULT_OBSERVER_MAX_QUEUED_PAYLOADS,
+                    connect_timeout_ms: DEFAULT_OBSERVER_CONNECT_TIMEOUT_MS,
+                    write_timeout_ms: DEFAULT_OBSERVER_WRITE_TIMEOUT_MS,
+                    tls_skip_verify: false,
+                    headers: HashMap::new(),
                 })
             },
             _ => ()
@@ -382,6 +437,10 @@ impl Config {
         format!("{}/chainstate/", self.node.working_dir)
     }
 
+    pub fn get_event_queue_path(&self) -> String {
+        format!("{}/event_queue/", self.node.working_dir)
+    }
+
     pub fn get_peer_db_path(&self) -> String {
         format!("{}/peer_db.sqlite", self.node.working_dir)
     }
@@ -633,24 +692,156 @@ pub struct NodeConfigFile {
 pub struct EventObserverConfigFile {
     pub endpoint: String,
     pub events_keys: Vec<String>,
+    pub transport: Option<String>,
+    pub tx_outcome_filter: Option<String>,
+    pub tx_type_filter: Option<Vec<String>>,
+    pub sample_rate: Option<f64>,
+    pub retry_count: Option<u32>,
+    pub retry_base_delay_ms: Option<u64>,
+    pub max_queued_payloads: Option<usize>,
+    pub connect_timeout_ms: Option<u64>,
+    pub write_timeout_ms: Option<u64>,
+    pub tls_skip_verify: Option<bool>,
+    pub headers: Option<HashMap<String, String>>,
 }
 
 #[derive(Clone, Default)]
 pub struct EventObserverConfig {
     pub endpoint: String,
     pub events_keys: Vec<EventKeyType>,
+    pub transport: EventTransportType,
+    pub tx_outcome_filter: TxOutcomeFilter,
+    /// Transaction types the observer receives; `None` means all of them.
+    pub tx_type_filter: Option<Vec<TxType>>,
+    /// Fraction of events (0.0 - 1.0) the observer receives; `None` means all of them.
+    pub sample_rate: Option<f64>,
+    /// How many times a failed delivery is retried, waiting `retry_base_delay_ms` before the
+    /// first retry and doubling the wait before each one after that.
+    pub retry_count: u32,
+    pub retry_base_delay_ms: u64,
+    /// Upper bound on undelivered block payloads kept on disk for replay; the oldest are
+    /// dropped past it, and 0 disables the queue.
+    pub max_queued_payloads: usize,
+    /// Timeouts for the HTTP transport.  The write timeout bounds the whole request, including
+    /// the wait for the response status.  0 means no timeout.
+    pub connect_timeout_ms: u64,
+    pub write_timeout_ms: u64,
+    /// With an `https://` endpoint, don't verify the server's certificate.  For self-signed
+    /// certificates in development only.
+    pub tls_skip_verify: bool,
+    /// Extra headers sent with every HTTP(S) request, e.g. an `Authorization` token.  Their
+    /// values are never logged.
+    pub headers: HashMap<String, String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TxType {
+    TokenTransfer,
+    ContractCall,
+    SmartContract,
+    PoisonMicroblock,
+    Coinbase,
+}
+
+impl TxType {
+    fn from_string(raw_type: &str) -> Option<TxType> {
+        match raw_type {
+            "token-transfer" => Some(TxType::TokenTransfer),
+            "contract-call" => Some(TxType::ContractCall),
+            "smart-contract" => Some(TxType::SmartContract),
+            "poison-microblock" => Some(TxType::PoisonMicroblock),
+            "coinbase" => Some(TxType::Coinbase),
+            _ => None
+        }
+    }
+
+    pub fn from_payload(payload: &TransactionPayload) -> TxType {
+        match payload {
+            TransactionPayload::TokenTransfer(..) => TxType::TokenTransfer,
+            TransactionPayload::ContractCall(_) => TxType::ContractCall,
+            TransactionPayload::SmartContract(_) => TxType::SmartContract,
+            TransactionPayload::PoisonMicroblock(..) => TxType::PoisonMicroblock,
+            TransactionPayload::Coinbase(_) => TxType::Coinbase,
+        }
+    }
+}
+
+/// How payloads are delivered to an observer's endpoint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventTransportType {
+    Http,
+    File,
+    Stdout,
+}
+
+impl Default for EventTransportType {
+    fn default() -> EventTransportType {
+        EventTransportType::Http
+    }
+}
+
+impl EventTransportType {
+    fn from_string(raw_transport: &str) -> Option<EventTransportType> {
+        match raw_transport {
+            // "tcp" predates the HTTP transport and is kept so existing configs still load
+            "http" | "tcp" => Some(EventTransportType::Http),
+            "file" => Some(EventTransportType::File),
+            "stdout" => Some(EventTransportType::Stdout),
+            _ => None
+        }
+    }
+}
+
+/// Restricts the transactions (and their events) an observer receives by outcome.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TxOutcomeFilter {
+    Success,
+    Failure,
+    All,
+}
+
+impl Default for TxOutcomeFilter {
+    fn default() -> TxOutcomeFilter {
+        TxOutcomeFilter::All
+    }
+}
+
+impl TxOutcomeFilter {
+    fn from_string(raw_filter: &str) -> Option<TxOutcomeFilter> {
+        match raw_filter {
+            "success" => Some(TxOutcomeFilter::Success),
+            "failure" => Some(TxOutcomeFilter::Failure),
+            "all" => Some(TxOutcomeFilter::All),
+            _ => None
+        }
+    }
+
+    pub fn accepts(&self, success: bool) -> bool {
+        match self {
+            TxOutcomeFilter::Success => success,
+            TxOutcomeFilter::Failure => !success,
+            TxOutcomeFilter::All => true,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub enum EventKeyType {
-    SmartContractEvent((QualifiedContractIdentifier, String)),
+    SmartContractEvent((QualifiedContractIdentifier, String), Option<EventValuePredicate>),
     AssetEvent(AssetIdentifier),
+    ContractsGlobEvent(StandardPrincipalData),
     STXEvent,
+    AllFungible,
+    AllNonFungible,
+    /// Operations parsed from each burnchain block, delivered to `/new_burn_block`
+    BurnchainOp,
+    /// Every event of the transactions sent by a principal, or calling or deploying a contract
+    SenderAddress(PrincipalData),
     AnyEvent,
 }
 
 impl EventKeyType {
-    fn from_string(raw_key: &str) -> Option<EventKeyType> {
+    pub fn from_string(raw_key: &str) -> Option<EventKeyType> {
         if raw_key == "*" {
             return Some(EventKeyType::AnyEvent);
         } 
@@ -658,6 +849,34 @@ impl EventKeyType {
         if raw_key == "stx" {
             return Some(EventKeyType::STXEvent);
         } 
+
+        if raw_key == "ft" {
+            return Some(EventKeyType::AllFungible);
+        }
+
+        if raw_key == "nft" {
+            return Some(EventKeyType::AllNonFungible);
+        }
+
+        if raw_key == "burnchain" {
+            return Some(EventKeyType::BurnchainOp);
+        }
+
+        if raw_key.starts_with("sender:") {
+            return match PrincipalData::parse(&raw_key["sender:".len()..]) {
+                Ok(principal) => Some(EventKeyType::SenderAddress(principal)),
+                Err(_) => None
+            };
+        }
+
+        // "<principal>.*" matches every contract deployed by <principal>
+        if raw_key.ends_with(".*") {
+            let deployer = &raw_key[..raw_key.len() - 2];
+            return match PrincipalData::parse_standard_principal(deployer) {
+                Ok(principal) => Some(EventKeyType::ContractsGlobEvent(principal)),
+                Err(_) => None
+            };
+        }
         
         let comps: Vec<_> = raw_key.split("::").collect();
         if comps.len() ==  1 {
@@ -675,8 +894,19 @@ impl EventKeyType {
                 (_, _, _) => None
             }
         } else if comps.len() == 2 {
+            // "<contract>::<key>?<field>=<value>" only matches events whose tuple payload
+            // has <field> equal to <value>
+            let (event_key, predicate) = match comps[1].find('?') {
+                Some(split_at) => {
+                    match EventValuePredicate::from_string(&comps[1][split_at + 1..]) {
+                        Some(predicate) => (&comps[1][..split_at], Some(predicate)),
+                        None => return None
+                    }
+                },
+                None => (comps[1], None)
+            };
             if let Ok(contract_identifier) = QualifiedContractIdentifier::parse(comps[0]) {
-                Some(EventKeyType::SmartContractEvent((contract_identifier, comps[1].to_string())))
+                Some(EventKeyType::SmartContractEvent((contract_identifier, event_key.to_string()), predicate))
             } else {
                 None
             }
@@ -686,6 +916,44 @@ impl EventKeyType {
     }
 }
 
+/// Equality check on a single top-level field of a print event's tuple payload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventValuePredicate {
+    pub field: String,
+    pub expected: String,
+}
+
+impl EventValuePredicate {
+    fn from_string(raw_predicate: &str) -> Option<EventValuePredicate> {
+        let comps: Vec<_> = raw_predicate.splitn(2, "=").collect();
+        if comps.len() != 2 || comps[0].is_empty() {
+            return None
+        }
+        Some(EventValuePredicate {
+            field: comps[0].to_string(),
+            expected: comps[1].to_string(),
+        })
+    }
+
+    /// The field's value is compared against its Clarity representation (e.g. `u100`, `true`,
+    /// `0x73776170`).  A quoted expected value (`"swap"`) is compared against a buffer's raw bytes.
+    pub fn matches(&self, value: &Value) -> bool {
+        let field_value = match value {
+            Value::Tuple(tuple_data) => match tuple_data.get(&self.field) {
+                Ok(field_value) => field_value,
+                Err(_) => return false
+            },
+            _ => return false
+        };
+        if let Value::Buffer(buff_data) = field_value {
+            if self.expected.len() >= 2 && self.expected.starts_with('"') && self.expected.ends_with('"') {
+                return buff_data.data[..] == self.expected.as_bytes()[1..self.expected.len() - 1];
+            }
+        }
+        format!("{}", field_value) == self.expected
+    }
+}
+
 #[derive(Clone)]
 pub struct InitialBalance {
     pub address: PrincipalData,
@@ -1,45 +1,480 @@
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
-use mio::tcp::TcpStream;
+use std::fmt;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
 use serde_json::json;
 
 use stacks::burnchains::Txid;
-use stacks::chainstate::stacks::events::{StacksTransactionEvent, STXEventType, FTEventType, NFTEventType};
+use stacks::chainstate::burn::{BlockHeaderHash, BlockSnapshot};
+use stacks::chainstate::burn::operations::BlockstackOperationType;
+use stacks::chainstate::stacks::{StacksMicroblock, TransactionPayload};
+use stacks::chainstate::stacks::events::{StacksTransactionEvent, StacksTransactionReceipt, STXEventType, FTEventType, NFTEventType};
 use stacks::net::StacksMessageCodec;
-use stacks::vm::types::{Value, QualifiedContractIdentifier, AssetIdentifier};
+use stacks::util::hash::{Sha512Trunc256Sum, to_hex};
+use stacks::vm::types::{Value, PrincipalData, StandardPrincipalData, QualifiedContractIdentifier, AssetIdentifier};
 use stacks::vm::analysis::{contract_interface_builder::build_contract_interface};
 
-use super::config::{EventObserverConfig, EventKeyType};
+use super::config::{EventObserverConfig, EventKeyType, EventTransportType, EventValuePredicate, TxOutcomeFilter, TxType};
 use super::node::{ChainTip};
 
+/// Version of the layout of dispatched payloads.  Bump it whenever that layout changes.
+///  1: initial layout
+///  2: transactions carry `execution_cost` and `fee`
+///  3: block payloads carry a per-observer `sequence`
+///  4: block payload events carry `microblock_announced`
+pub const EVENT_PAYLOAD_SCHEMA_VERSION: u32 = 4;
+
+/// Delivers serialized payloads to an observer's endpoint.  `path` names the hook the payload is
+/// for (e.g. `/new_block`); transports without a notion of paths ignore it.  A payload the
+/// observer refuses for good fails with a PayloadRejected error (see payload_rejected()); any
+/// other error is taken to be transient.
+pub trait EventTransport: fmt::Debug + Send {
+    fn send_payload(&mut self, endpoint: &str, path: &str, payload: &[u8]) -> io::Result<()>;
+}
+
+/// The observer refused a payload in a way that sending it again won't change, e.g. with a 400
+/// or 401 response.  Such payloads are dropped rather than retried or queued, since a queued one
+/// would hold up every block behind it.
+#[derive(Debug)]
+pub struct PayloadRejected(pub String);
+
+impl fmt::Display for PayloadRejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for PayloadRejected {}
+
+impl PayloadRejected {
+    pub fn into_io_error(self) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, self)
+    }
+}
+
+/// Whether a transport error means the payload was refused for good.
+fn payload_rejected(err: &io::Error) -> bool {
+    match err.get_ref() {
+        Some(inner) => inner.is::<PayloadRejected>(),
+        None => false
+    }
+}
+
+/// POSTs each payload as JSON to an `http://` or `https://` endpoint.  An endpoint without a
+/// scheme is taken to be `http://host:port`, optionally followed by a base path that the hook's
+/// path is appended to.  With https, the server's certificate is verified unless the observer is
+/// configured with `tls_skip_verify`.
+pub struct HttpTransport {
+    client: reqwest::blocking::Client,
+    /// Extra headers sent with every request.
+    headers: Vec<(String, String)>,
+}
+
+impl HttpTransport {
+    /// `timeout` bounds the whole request, from connecting until the response status arrives.
+    pub fn new(connect_timeout: Option<Duration>, timeout: Option<Duration>, skip_verify: bool, headers: Vec<(String, String)>) -> io::Result<HttpTransport> {
+        let client = reqwest::blocking::Client::builder()
+            .connect_timeout(connect_timeout)
+            .timeout(timeout)
+            .danger_accept_invalid_certs(skip_verify)
+            .build()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to build HTTP client: {}", e)))?;
+        Ok(HttpTransport { client, headers })
+    }
+
+    fn url(endpoint: &str, path: &str) -> String {
+        let endpoint = endpoint.trim_end_matches('/');
+        if endpoint.starts_with("http://") || endpoint.starts_with("https://") {
+            format!("{}{}", endpoint, path)
+        } else {
+            format!("http://{}{}", endpoint, path)
+        }
+    }
+}
+
+impl fmt::Debug for HttpTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpTransport")
+            .field("client", &self.client)
+            .field("headers", &redacted_headers(&self.headers))
+            .finish()
+    }
+}
+
+impl EventTransport for HttpTransport {
+    fn send_payload(&mut self, endpoint: &str, path: &str, payload: &[u8]) -> io::Result<()> {
+        let url = HttpTransport::url(endpoint, path);
+        let mut request = self.client.post(&url)
+            .header("Content-Type", "application/json");
+        for (name, value) in self.headers.iter() {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        let response = request
+            .body(payload.to_vec())
+            .send()
+            .map_err(|e| {
+                let kind = if e.is_timeout() { io::ErrorKind::TimedOut } else { io::ErrorKind::Other };
+                io::Error::new(kind, format!("Failed sending to event observer at {}: {}", url, e))
+            })?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else if status.is_client_error() && status != reqwest::StatusCode::REQUEST_TIMEOUT && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+            Err(PayloadRejected(format!("Event observer at {} rejected the payload with: {}", url, status)).into_io_error())
+        } else {
+            Err(io::Error::new(io::ErrorKind::Other,
+                               format!("Event observer at {} responded with: {}", url, status)))
+        }
+    }
+}
+
+/// Appends each payload as one line to the file at the endpoint path.  Pointing it at a named
+/// pipe lets a sidecar bridge events to a message broker.
+#[derive(Debug)]
+pub struct FileTransport;
+
+impl EventTransport for FileTransport {
+    fn send_payload(&mut self, endpoint: &str, _path: &str, payload: &[u8]) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(endpoint)?;
+        file.write_all(payload)?;
+        file.write_all(b"\n")?;
+        file.flush()
+    }
+}
+
+/// Writes each payload as one line to the process' stdout.  The endpoint is ignored.
+#[derive(Debug)]
+pub struct StdoutTransport;
+
+impl EventTransport for StdoutTransport {
+    fn send_payload(&mut self, _endpoint: &str, _path: &str, payload: &[u8]) -> io::Result<()> {
+        let stdout = io::stdout();
+        let mut handle = stdout.lock();
+        handle.write_all(payload)?;
+        handle.write_all(b"\n")?;
+        handle.flush()
+    }
+}
+
+/// On-disk queue of block payloads that an observer failed to receive.  Each payload is kept in
+/// its own file named after the block's height and index block hash, so the queue survives node
+/// restarts and replays in block order.
+#[derive(Debug)]
+struct PayloadQueue {
+    dir: PathBuf,
+    max_len: usize,
+}
+
+impl PayloadQueue {
+    fn open(dir: PathBuf, max_len: usize) -> io::Result<PayloadQueue> {
+        fs::create_dir_all(&dir)?;
+        Ok(PayloadQueue { dir, max_len })
+    }
+
+    /// Queued payload files, oldest block first.
+    fn entries(&self) -> io::Result<Vec<PathBuf>> {
+        let mut entries = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().map(|ext| ext == "json").unwrap_or(false) {
+                entries.push(path);
+            }
+        }
+        entries.sort();
+        Ok(entries)
+    }
+
+    fn push(&self, block_height: u64, index_block_hash: &str, payload: &[u8]) -> io::Result<()> {
+        if self.max_len == 0 {
+            return Ok(());
+        }
+
+        // Write then rename, so a crash mid-write never leaves a truncated payload to replay
+        let file_name = format!("{:020}-{}", block_height, index_block_hash);
+        let tmp_path = self.dir.join(format!("{}.tmp", file_name));
+        fs::write(&tmp_path, payload)?;
+        fs::rename(&tmp_path, self.dir.join(format!("{}.json", file_name)))?;
+
+        let entries = self.entries()?;
+        if entries.len() > self.max_len {
+            for dropped in entries[..entries.len() - self.max_len].iter() {
+                warn!("Event observer queue at {:?} is full, dropping {:?}", self.dir, dropped);
+                fs::remove_file(dropped)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The queue directory of the observer at `endpoint`.  It is named after a hash of the endpoint,
+/// so an observer finds its own undelivered payloads again after a restart, whatever order the
+/// observers are registered in, and never picks up another observer's.
+fn observer_queue_dir(queue_path: &str, endpoint: &str) -> PathBuf {
+    let endpoint_hash = Sha512Trunc256Sum::from_data(endpoint.as_bytes());
+    PathBuf::from(queue_path).join(to_hex(&endpoint_hash.as_bytes()[0..16]))
+}
+
+/// The sequence number of the last block dispatched to the observer owning the queue directory
+/// `dir`, or 0 if none was.
+fn load_sequence(dir: &Path) -> io::Result<u64> {
+    match fs::read_to_string(dir.join("sequence")) {
+        Ok(contents) => contents.trim().parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Bad sequence file in {:?}: {}", dir, e))),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err)
+    }
+}
+
+fn store_sequence(dir: &Path, sequence: u64) -> io::Result<()> {
+    let tmp_path = dir.join("sequence.tmp");
+    fs::write(&tmp_path, sequence.to_string())?;
+    fs::rename(&tmp_path, dir.join("sequence"))
+}
+
+/// Work handed to an observer's delivery thread.
+#[derive(Debug)]
+enum DeliveryRequest {
+    Block { block_height: u64, index_block_hash: String, payload: String },
+    Microblock { payload: String },
+    BurnBlock { payload: String },
+    /// Sent without retries or queueing; the result goes back through the channel.
+    Test { payload: String, result: Sender<io::Result<()>> },
+    /// Answered once every request sent before it has been handled.
+    Flush(Sender<()>),
+}
+
+/// The delivery side of an observer.  Each observer gets its own thread running one of these, so
+/// payloads reach an observer in the order they were dispatched, while a slow observer holds up
+/// neither block processing nor the other observers.
 #[derive(Debug)]
+struct ObserverDelivery {
+    endpoint: String,
+    transport: Box<dyn EventTransport>,
+    retry_count: u32,
+    retry_base_delay_ms: u64,
+    queue: Option<PayloadQueue>,
+}
+
+/// Check the configured headers of an observer and put them in a stable order.  Names and values
+/// go into the request as-is, so anything that could break out of a header line is refused.
+fn parse_observer_headers(raw_headers: &HashMap<String, String>) -> Result<Vec<(String, String)>, String> {
+    let mut headers = vec![];
+    for (name, value) in raw_headers.iter() {
+        let name_ok = !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+        if !name_ok {
+            return Err(format!("Invalid header name {:?}", name));
+        }
+        if value.bytes().any(|b| b == b'\r' || b == b'\n' || b == 0) {
+            return Err(format!("Invalid value for header {}", name));
+        }
+        headers.push((name.clone(), value.clone()));
+    }
+    headers.sort();
+    Ok(headers)
+}
+
+/// Header names with their values blanked out, so tokens never end up in the node's logs.
+fn redacted_headers(headers: &[(String, String)]) -> String {
+    headers.iter()
+        .map(|(name, _)| format!("{}: <redacted>", name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl ObserverDelivery {
+
+    fn run(mut self, requests: Receiver<DeliveryRequest>) {
+        while let Ok(request) = requests.recv() {
+            match request {
+                DeliveryRequest::Block { block_height, index_block_hash, payload } => {
+                    if let Err(err) = self.deliver_block(block_height, &index_block_hash, &payload) {
+                        error!("Event dispatcher failed sending block to observer at {}: {:?}", self.endpoint, err);
+                    }
+                },
+                DeliveryRequest::Microblock { payload } => {
+                    // Not queued on failure: if the microblock gets confirmed, the anchored
+                    // block's payload carries its transactions anyway.
+                    if let Err(err) = self.send_with_retry("/new_microblock", payload.as_bytes()) {
+                        error!("Event dispatcher failed sending microblock to observer at {}: {:?}", self.endpoint, err);
+                    }
+                },
+                DeliveryRequest::BurnBlock { payload } => {
+                    if let Err(err) = self.send_with_retry("/new_burn_block", payload.as_bytes()) {
+                        error!("Event dispatcher failed sending burn block to observer at {}: {:?}", self.endpoint, err);
+                    }
+                },
+                DeliveryRequest::Test { payload, result } => {
+                    let _ = result.send(self.transport.send_payload(&self.endpoint, "/new_block", payload.as_bytes()));
+                },
+                DeliveryRequest::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    fn deliver_block(&mut self, block_height: u64, index_block_hash: &str, payload: &str) -> io::Result<()> {
+        // Blocks must reach the observer in order, so while older blocks are still undelivered
+        // this one waits in the queue behind them.
+        if let Err(err) = self.flush_queue() {
+            self.enqueue(block_height, index_block_hash, payload.as_bytes());
+            return Err(err);
+        }
+
+        match self.send_with_retry("/new_block", payload.as_bytes()) {
+            Err(ref err) if payload_rejected(err) => {
+                error!("Event observer at {} rejected block {} at height {}; dropping it: {}", self.endpoint, index_block_hash, block_height, err);
+                Ok(())
+            },
+            Err(err) => {
+                self.enqueue(block_height, index_block_hash, payload.as_bytes());
+                Err(err)
+            },
+            Ok(()) => Ok(())
+        }
+    }
+
+    /// Replay queued payloads, oldest first, removing each once delivered or rejected for good.
+    /// Stops at the first transient failure; it will be attempted again with the next block.
+    fn flush_queue(&mut self) -> io::Result<()> {
+        let queue = match self.queue {
+            Some(ref queue) => queue,
+            None => return Ok(())
+        };
+        for entry in queue.entries()? {
+            let payload = fs::read(&entry)?;
+            match self.transport.send_payload(&self.endpoint, "/new_block", &payload) {
+                Ok(()) => info!("Replayed queued payload {:?} to event observer at {}", entry, self.endpoint),
+                Err(ref err) if payload_rejected(err) => {
+                    error!("Event observer at {} rejected queued payload {:?}; dropping it: {}", self.endpoint, entry, err);
+                },
+                Err(err) => return Err(err)
+            }
+            fs::remove_file(&entry)?;
+        }
+        Ok(())
+    }
+
+    fn enqueue(&self, block_height: u64, index_block_hash: &str, payload: &[u8]) {
+        if let Some(ref queue) = self.queue {
+            if let Err(err) = queue.push(block_height, index_block_hash, payload) {
+                error!("Failed to queue undelivered payload for event observer at {}: {:?}", self.endpoint, err);
+            }
+        }
+    }
+
+    /// Send a payload, retrying failed deliveries with exponential backoff.  Returns the last
+    /// error once the retries are exhausted, or right away if the payload was rejected.
+    fn send_with_retry(&mut self, path: &str, payload: &[u8]) -> io::Result<()> {
+        let mut attempt = 0;
+        loop {
+            match self.transport.send_payload(&self.endpoint, path, payload) {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    if attempt >= self.retry_count || payload_rejected(&err) {
+                        return Err(err);
+                    }
+                    let delay_ms = self.retry_base_delay_ms.saturating_mul(1u64 << attempt.min(16));
+                    warn!("Failed sending to event observer at {} ({:?}); retrying in {}ms", self.endpoint, err, delay_ms);
+                    thread::sleep(Duration::from_millis(delay_ms));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
 struct EventObserver {
-    endpoint: String
+    endpoint: String,
+    tx_outcome_filter: TxOutcomeFilter,
+    tx_type_filter: Option<Vec<TxType>>,
+    sample_rate: Option<f64>,
+    queue_dir: Option<PathBuf>,
+    /// Sequence number of the last block dispatched to the observer.  Numbers are handed out as
+    /// blocks are dispatched, so a block that is never delivered (dropped from a full queue, or
+    /// lost at shutdown) shows up as a gap.  It is kept in the observer's queue directory, if it
+    /// has one, so it carries over restarts.
+    sequence: AtomicU64,
+    /// Extra headers the transport sends with every request.  Only the names may be logged.
+    headers: Vec<(String, String)>,
+    delivery: Sender<DeliveryRequest>,
+}
+
+impl fmt::Debug for EventObserver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EventObserver")
+            .field("endpoint", &self.endpoint)
+            .field("tx_outcome_filter", &self.tx_outcome_filter)
+            .field("tx_type_filter", &self.tx_type_filter)
+            .field("sample_rate", &self.sample_rate)
+            .field("queue_dir", &self.queue_dir)
+            .field("sequence", &self.sequence)
+            .field("headers", &redacted_headers(&self.headers))
+            .field("delivery", &self.delivery)
+            .finish()
+    }
 }
 
 impl EventObserver {
 
-    pub fn send(&mut self, filtered_events: Vec<&(Txid, &StacksTransactionEvent)>, chain_tip: &ChainTip) {
-        // Initiate a tcp socket, first using std::net TCP connect for smart DNS resolution
-        let std_stream = std::net::TcpStream::connect(&self.endpoint).unwrap();
-        info!("Connected to event observer at: {}", std_stream.peer_addr().unwrap());
+    fn accepts_receipt(&self, receipt: &StacksTransactionReceipt) -> bool {
+        if let Some(ref tx_types) = self.tx_type_filter {
+            if !tx_types.contains(&TxType::from_payload(&receipt.transaction.payload)) {
+                return false;
+            }
+        }
 
-        // Then wrap as mio TCP stream
-        let stream = TcpStream::from_stream(std_stream).unwrap();
-        // Serialize events to JSON
-        let serialized_events: Vec<serde_json::Value> = filtered_events.iter().map(|(txid, event)|
-            event.json_serialize(txid)
-        ).collect();
+        let success = match &receipt.result {
+            Value::Response(response_data) => response_data.committed,
+            _ => true,
+        };
+        self.tx_outcome_filter.accepts(success)
+    }
 
-        let mut tx_index: u32 = 0;
-        let serialized_txs: Vec<serde_json::Value> = chain_tip.receipts.iter().map(|receipt| {
+    /// Decide whether a sampling observer receives an event.  The decision only depends on the
+    /// event's txid and position within its transaction, so it is the same across restarts.
+    fn samples_event(&self, txid: &Txid, event_index: usize) -> bool {
+        let sample_rate = match self.sample_rate {
+            Some(sample_rate) => sample_rate,
+            None => return true
+        };
+
+        let mut event_id = txid.as_bytes().to_vec();
+        event_id.extend_from_slice(&(event_index as u64).to_be_bytes());
+        let digest = Sha512Trunc256Sum::from_data(&event_id);
+
+        let mut head = [0u8; 8];
+        head.copy_from_slice(&digest.as_bytes()[0..8]);
+        (u64::from_be_bytes(head) as f64) < sample_rate * (u64::max_value() as f64)
+    }
+
+    /// Serialize the transactions this observer accepts.  With `microblock_announced_txids`, each
+    /// transaction is flagged with whether it was already dispatched as part of a microblock.
+    fn serialize_txs(&self, receipts: &[StacksTransactionReceipt], microblock_announced_txids: Option<&HashSet<Txid>>) -> Vec<serde_json::Value> {
+        receipts.iter().enumerate()
+            .filter(|(_, receipt)| self.accepts_receipt(receipt))
+            .map(|(tx_index, receipt)| {
             let tx = &receipt.transaction;
 
             let (success, result) = match &receipt.result {
                 Value::Response(response_data) => {
                     (response_data.committed, response_data.data.clone())
                 },
-                _ => unreachable!(), // Transaction results should always be a Value::Response type
+                // Transaction results should always be a Value::Response type, but one that isn't
+                // must not take down dispatching for the whole block.
+                other => {
+                    warn!("Transaction {} has a non-response result {}; dispatching it as successful", tx.txid(), other);
+                    (true, Box::new(other.clone()))
+                }
             };
 
             let raw_tx = {
@@ -61,130 +496,684 @@ impl EventObserver {
                     None => json!(null)
                 }
             };
-            let val = json!({
+            let mut tx_json = json!({
                 "txid": format!("0x{}", tx.txid()),
                 "tx_index": tx_index,
                 "success": success,
                 "raw_result": format!("0x{}", raw_result.join("")),
                 "raw_tx": format!("0x{}", raw_tx.join("")),
                 "contract_abi": contract_interface_json,
+                "execution_cost": {
+                    "runtime": receipt.execution_cost.runtime,
+                    "read_count": receipt.execution_cost.read_count,
+                    "read_length": receipt.execution_cost.read_length,
+                    "write_count": receipt.execution_cost.write_count,
+                    "write_length": receipt.execution_cost.write_length,
+                },
+                "fee": tx.get_fee_rate(),
             });
-            tx_index += 1;
-            val
+            if let Some(announced_txids) = microblock_announced_txids {
+                tx_json["microblock_announced"] = json!(announced_txids.contains(&tx.txid()));
+            }
+            tx_json
+        }).collect()
+    }
+
+    /// Hand out the sequence number of the next block dispatched to the observer.
+    fn next_sequence(&self) -> u64 {
+        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(ref queue_dir) = self.queue_dir {
+            if let Err(err) = store_sequence(queue_dir, sequence) {
+                error!("Failed to persist sequence number for event observer at {}: {:?}", self.endpoint, err);
+            }
+        }
+        sequence
+    }
+
+    pub fn send(&self, filtered_events: Vec<&(Txid, &StacksTransactionEvent)>, chain_tip: &ChainTip, contract_deploys: &Vec<serde_json::Value>, microblock_announced_txids: &HashSet<Txid>) -> io::Result<()> {
+        // Serialize events to JSON.  Events of transactions already sent on `/new_microblock` are
+        // flagged, so consumers of both hooks don't count them twice.
+        let serialized_events: Vec<serde_json::Value> = filtered_events.iter().map(|(txid, event)| {
+            let mut event_json = event.json_serialize(txid);
+            event_json["microblock_announced"] = json!(microblock_announced_txids.contains(txid));
+            event_json
         }).collect();
+
+        let serialized_txs = self.serialize_txs(&chain_tip.receipts, Some(microblock_announced_txids));
         
-        // Wrap events
+        // Wrap events.  The idempotency key only depends on the block's index hash, so a
+        // re-dispatch of the same block carries the same key and consumers can dedupe on it.
         let payload = json!({
+            "schema_version": EVENT_PAYLOAD_SCHEMA_VERSION,
             "block_hash": format!("0x{:?}", chain_tip.block.block_hash()),
             "block_height": chain_tip.metadata.block_height,
             "index_block_hash": format!("0x{:?}", chain_tip.metadata.index_block_hash()),
             "parent_block_hash": format!("0x{:?}", chain_tip.block.header.parent_block),
             "parent_microblock": format!("0x{:?}", chain_tip.block.header.parent_microblock),
+            "burn_block_height": chain_tip.burn_block_height,
+            "burn_block_hash": format!("0x{:?}", chain_tip.metadata.burn_header_hash),
+            "burn_block_time": chain_tip.metadata.burn_header_timestamp,
             "events": serialized_events,
             "transactions": serialized_txs,
+            "contract_deploys": contract_deploys,
+            "idempotency_key": format!("0x{:?}", chain_tip.metadata.index_block_hash()),
+            "sequence": self.next_sequence(),
         }).to_string();
 
-        // Send payload
-        let res = stream.write_bufs(&vec![payload.as_bytes().into()]);
-        if let Err(err) = res {
-            error!("Event dispatcher failed sending buffer: {:?}", err);
-            panic!();
+        self.deliver(DeliveryRequest::Block {
+            block_height: chain_tip.metadata.block_height,
+            index_block_hash: format!("{:?}", chain_tip.metadata.index_block_hash()),
+            payload,
+        })
+    }
+
+    pub fn send_microblock(&self, filtered_events: Vec<&(Txid, &StacksTransactionEvent)>, microblock_hash: &BlockHeaderHash, parent_index_block_hash: &BlockHeaderHash, receipts: &[StacksTransactionReceipt]) -> io::Result<()> {
+        let serialized_events: Vec<serde_json::Value> = filtered_events.iter().map(|(txid, event)|
+            event.json_serialize(txid)
+        ).collect();
+
+        let serialized_txs = self.serialize_txs(receipts, None);
+
+        let payload = json!({
+            "schema_version": EVENT_PAYLOAD_SCHEMA_VERSION,
+            "microblock_hash": format!("0x{:?}", microblock_hash),
+            "parent_index_block_hash": format!("0x{:?}", parent_index_block_hash),
+            "events": serialized_events,
+            "transactions": serialized_txs,
+            "idempotency_key": format!("0x{:?}", microblock_hash),
+        }).to_string();
+
+        self.deliver(DeliveryRequest::Microblock { payload })
+    }
+
+    pub fn send_burn_block(&self, block_snapshot: &BlockSnapshot, serialized_ops: &Vec<serde_json::Value>) -> io::Result<()> {
+        let payload = json!({
+            "schema_version": EVENT_PAYLOAD_SCHEMA_VERSION,
+            "burn_block_height": block_snapshot.block_height,
+            "burn_block_hash": format!("0x{:?}", block_snapshot.burn_header_hash),
+            "parent_burn_block_hash": format!("0x{:?}", block_snapshot.parent_burn_header_hash),
+            "burn_block_time": block_snapshot.burn_header_timestamp,
+            "operations": serialized_ops,
+            "idempotency_key": format!("0x{:?}", block_snapshot.burn_header_hash),
+        }).to_string();
+
+        self.deliver(DeliveryRequest::BurnBlock { payload })
+    }
+
+    /// Hand a request to the observer's delivery thread.  Only fails if that thread is gone.
+    fn deliver(&self, request: DeliveryRequest) -> io::Result<()> {
+        self.delivery.send(request)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, format!("Delivery thread for event observer at {} has exited", self.endpoint)))
+    }
+}
+
+/// Number of events of each type in a block, as counted by EventDispatcher::summarize_events().
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventSummary {
+    pub smart_contract: u64,
+    pub stx_transfer: u64,
+    pub stx_mint: u64,
+    pub stx_burn: u64,
+    pub nft_transfer: u64,
+    pub nft_mint: u64,
+    pub ft_transfer: u64,
+    pub ft_mint: u64,
+}
+
+impl EventSummary {
+    pub fn total(&self) -> u64 {
+        self.smart_contract + self.stx_transfer + self.stx_mint + self.stx_burn
+            + self.nft_transfer + self.nft_mint + self.ft_transfer + self.ft_mint
+    }
+}
+
+/// Which events of a block each observer receives.  Observers subscribed to every event are only
+/// flagged, instead of having every event's index recorded; the indexes are listed once the
+/// observer's payload is built.
+struct DispatchMatrix {
+    all_events: Vec<bool>,
+    event_ids: Vec<HashSet<usize>>,
+}
+
+impl DispatchMatrix {
+    fn new(observer_count: usize) -> DispatchMatrix {
+        DispatchMatrix {
+            all_events: vec![false; observer_count],
+            event_ids: (0..observer_count).map(|_| HashSet::new()).collect(),
+        }
+    }
+
+    fn insert_all(&mut self, observer_index: u16) {
+        self.all_events[observer_index as usize] = true;
+    }
+
+    fn insert(&mut self, observer_index: u16, event_id: usize) {
+        if !self.all_events[observer_index as usize] {
+            self.event_ids[observer_index as usize].insert(event_id);
         }
     }
+
+    /// The indexes of the events the observer receives, in block order.
+    fn event_ids(&self, observer_index: u16, event_count: usize) -> Vec<usize> {
+        if self.all_events[observer_index as usize] {
+            return (0..event_count).collect();
+        }
+        let mut event_ids: Vec<usize> = self.event_ids[observer_index as usize].iter().cloned().collect();
+        event_ids.sort();
+        event_ids
+    }
+}
+
+/// How many blocks past its parent anchored block a dispatched microblock is remembered.  An
+/// anchored block that confirms an older stream gets its transactions unflagged.
+const MICROBLOCK_ANNOUNCEMENT_DEPTH: u64 = 10;
+
+/// A microblock whose transactions were dispatched to `/new_microblock`.
+struct AnnouncedMicroblock {
+    prev_block: BlockHeaderHash,
+    parent_block_height: u64,
+    txids: Vec<Txid>,
 }
 
 pub struct EventDispatcher {
     registered_observers: Vec<EventObserver>,
+    queue_path: Option<String>,
+    announced_microblocks: HashMap<BlockHeaderHash, AnnouncedMicroblock>,
     contract_events_observers_lookup: HashMap<(QualifiedContractIdentifier, String), HashSet<u16>>,
+    contract_events_predicate_observers_lookup: HashMap<(QualifiedContractIdentifier, String), Vec<(u16, EventValuePredicate)>>,
     assets_observers_lookup: HashMap<AssetIdentifier, HashSet<u16>>,
+    contracts_glob_observers_lookup: HashMap<StandardPrincipalData, HashSet<u16>>,
     stx_observers_lookup: HashSet<u16>,
+    ft_observers_lookup: HashSet<u16>,
+    nft_observers_lookup: HashSet<u16>,
+    burnchain_ops_observers_lookup: HashSet<u16>,
+    sender_observers_lookup: HashMap<PrincipalData, HashSet<u16>>,
     any_event_observers_lookup: HashSet<u16>,
 }
 
 impl EventDispatcher {
 
-    pub fn new() -> EventDispatcher {
+    /// `queue_path` is the directory under which undelivered payloads are kept, one
+    /// subdirectory per observer endpoint.  With `None`, undelivered payloads are dropped.
+    pub fn new(queue_path: Option<String>) -> EventDispatcher {
         EventDispatcher {
             registered_observers: vec![],
+            queue_path,
+            announced_microblocks: HashMap::new(),
             contract_events_observers_lookup: HashMap::new(),
+            contract_events_predicate_observers_lookup: HashMap::new(),
             assets_observers_lookup: HashMap::new(),
+            contracts_glob_observers_lookup: HashMap::new(),
             stx_observers_lookup: HashSet::new(),
+            ft_observers_lookup: HashSet::new(),
+            nft_observers_lookup: HashSet::new(),
+            burnchain_ops_observers_lookup: HashSet::new(),
+            sender_observers_lookup: HashMap::new(),
             any_event_observers_lookup: HashSet::new(),
         }
     }
 
+    /// List the contracts deployed in a block, and whether each deploy passed analysis.
+    fn serialize_contract_deploys(chain_tip: &ChainTip) -> Vec<serde_json::Value> {
+        chain_tip.receipts.iter().filter_map(|receipt| {
+            match &receipt.transaction.payload {
+                TransactionPayload::SmartContract(smart_contract) => {
+                    let issuer = StandardPrincipalData::from(receipt.transaction.origin_address());
+                    let contract_id = QualifiedContractIdentifier::new(issuer, smart_contract.name.clone());
+                    Some(json!({
+                        "contract_id": contract_id.to_string(),
+                        "abi_ok": receipt.contract_analysis.is_some(),
+                    }))
+                },
+                _ => None
+            }
+        }).collect()
+    }
+
     pub fn process_chain_tip(&mut self, chain_tip: &ChainTip) {
+        let contract_deploys = EventDispatcher::serialize_contract_deploys(chain_tip);
+
+        // Only the stream this block confirms counts; a sibling fork may confirm another one.
+        let microblock_announced_txids = self.announced_txids(&chain_tip.block.header.parent_microblock);
+
+        self.dispatch_receipts(&chain_tip.receipts, |observer, filtered_events| {
+            observer.send(filtered_events, chain_tip, &contract_deploys, &microblock_announced_txids)
+        });
+
+        // Streams stay around for a while, since another fork may still confirm them.
+        let tip_height = chain_tip.metadata.block_height;
+        self.announced_microblocks.retain(|_, announced| announced.parent_block_height + MICROBLOCK_ANNOUNCEMENT_DEPTH >= tip_height);
+    }
 
-        let mut dispatch_matrix: Vec<HashSet<usize>> = self.registered_observers.iter().map(|_| HashSet::new()).collect();
+    /// The transactions dispatched in the microblock stream that ends at `parent_microblock`.
+    fn announced_txids(&self, parent_microblock: &BlockHeaderHash) -> HashSet<Txid> {
+        let mut txids = HashSet::new();
+        let mut microblock_hash = parent_microblock.clone();
+        while let Some(announced) = self.announced_microblocks.get(&microblock_hash) {
+            txids.extend(announced.txids.iter().cloned());
+            microblock_hash = announced.prev_block.clone();
+        }
+        txids
+    }
+
+    /// Dispatch the operations accepted in a burnchain block, POSTed to `/new_burn_block`, to the
+    /// observers subscribed to burnchain operations or to every event.
+    pub fn process_burn_block(&self, block_snapshot: &BlockSnapshot, ops: &[BlockstackOperationType]) {
+        let observer_indexes: HashSet<_> = self.burnchain_ops_observers_lookup.union(&self.any_event_observers_lookup).collect();
+        if observer_indexes.is_empty() {
+            return;
+        }
+
+        let serialized_ops: Vec<serde_json::Value> = ops.iter().map(EventDispatcher::serialize_burnchain_op).collect();
+        for o_i in observer_indexes {
+            let observer = &self.registered_observers[*o_i as usize];
+            if let Err(err) = observer.send_burn_block(block_snapshot, &serialized_ops) {
+                error!("Event dispatcher failed sending to observer at {}: {:?}", observer.endpoint, err);
+            }
+        }
+    }
+
+    fn serialize_burnchain_op(op: &BlockstackOperationType) -> serde_json::Value {
+        match op {
+            BlockstackOperationType::LeaderKeyRegister(op) => json!({
+                "op": "leader_key_register",
+                "txid": format!("0x{}", op.txid),
+                "vtxindex": op.vtxindex,
+                "consensus_hash": format!("0x{:?}", op.consensus_hash),
+                "public_key": format!("0x{}", op.public_key.to_hex()),
+                "address": op.address.to_string(),
+                "memo": format!("0x{}", to_hex(&op.memo)),
+            }),
+            BlockstackOperationType::LeaderBlockCommit(op) => json!({
+                "op": "leader_block_commit",
+                "txid": format!("0x{}", op.txid),
+                "vtxindex": op.vtxindex,
+                "block_hash": format!("0x{:?}", op.block_header_hash),
+                "new_seed": format!("0x{:?}", op.new_seed),
+                "parent_block_ptr": op.parent_block_ptr,
+                "parent_vtxindex": op.parent_vtxindex,
+                "key_block_ptr": op.key_block_ptr,
+                "key_vtxindex": op.key_vtxindex,
+                "burn_fee": op.burn_fee,
+                "input": op.input,
+                "memo": format!("0x{}", to_hex(&op.memo)),
+            }),
+            BlockstackOperationType::UserBurnSupport(op) => json!({
+                "op": "user_burn_support",
+                "txid": format!("0x{}", op.txid),
+                "vtxindex": op.vtxindex,
+                "address": op.address.to_string(),
+                "consensus_hash": format!("0x{:?}", op.consensus_hash),
+                "public_key": format!("0x{}", op.public_key.to_hex()),
+                "key_block_ptr": op.key_block_ptr,
+                "key_vtxindex": op.key_vtxindex,
+                "block_hash_160": format!("0x{:?}", op.block_header_hash_160),
+                "burn_fee": op.burn_fee,
+            }),
+        }
+    }
+
+    /// Dispatch the events of the transactions in a microblock, POSTed to `/new_microblock`.
+    /// When the anchored block that confirms them is dispatched, these transactions are flagged
+    /// with `"microblock_announced": true` so consumers don't count them twice.  Transactions of a
+    /// microblock that gets orphaned never show up in a `/new_block` payload at all.
+    pub fn process_microblock(&mut self, microblock: &StacksMicroblock, parent_index_block_hash: &BlockHeaderHash, parent_block_height: u64, receipts: &[StacksTransactionReceipt]) {
+        let microblock_hash = microblock.block_hash();
+        self.announced_microblocks.insert(microblock_hash.clone(), AnnouncedMicroblock {
+            prev_block: microblock.header.prev_block.clone(),
+            parent_block_height,
+            txids: receipts.iter().map(|receipt| receipt.transaction.txid()).collect(),
+        });
+
+        self.dispatch_receipts(receipts, |observer, filtered_events| {
+            observer.send_microblock(filtered_events, &microblock_hash, parent_index_block_hash, receipts)
+        });
+    }
+
+    /// Dispatch the microblocks `chain_tip` confirms, ahead of the block itself.  Their receipts
+    /// follow the anchored block's own receipts in `chain_tip.receipts`, in stream order.
+    pub fn process_confirmed_microblocks(&mut self, chain_tip: &ChainTip, parent_index_block_hash: &BlockHeaderHash, microblocks: &[StacksMicroblock]) {
+        let parent_block_height = chain_tip.metadata.block_height.saturating_sub(1);
+        let mut start = chain_tip.block.txs.len();
+        for microblock in microblocks.iter() {
+            let end = start + microblock.txs.len();
+            if end > chain_tip.receipts.len() {
+                warn!("Block {} has fewer receipts than the microblocks it confirms; not dispatching microblock {}",
+                      chain_tip.block.block_hash(), microblock.block_hash());
+                return;
+            }
+            self.process_microblock(microblock, parent_index_block_hash, parent_block_height, &chain_tip.receipts[start..end]);
+            start = end;
+        }
+    }
+
+    /// Visit every event in `receipts` in block order, along with the receipt and txid of its
+    /// transaction and its position within that transaction.  Dispatch and summarize_events()
+    /// share this walk so that they always agree on which events a block produced.
+    fn walk_events<'a, F>(receipts: &'a [StacksTransactionReceipt], mut visit: F)
+    where F: FnMut(&'a StacksTransactionReceipt, Txid, usize, &'a StacksTransactionEvent) {
+        for receipt in receipts.iter() {
+            let tx_hash = receipt.transaction.txid();
+            for (event_index, event) in receipt.events.iter().enumerate() {
+                visit(receipt, tx_hash.clone(), event_index, event);
+            }
+        }
+    }
+
+    /// Count the events of each type in a block, without dispatching anything.
+    pub fn summarize_events(chain_tip: &ChainTip) -> EventSummary {
+        let mut summary = EventSummary::default();
+        EventDispatcher::walk_events(&chain_tip.receipts, |_, _, _, event| {
+            match event {
+                StacksTransactionEvent::SmartContractEvent(_) => summary.smart_contract += 1,
+                StacksTransactionEvent::STXEvent(STXEventType::STXTransferEvent(_)) => summary.stx_transfer += 1,
+                StacksTransactionEvent::STXEvent(STXEventType::STXMintEvent(_)) => summary.stx_mint += 1,
+                StacksTransactionEvent::STXEvent(STXEventType::STXBurnEvent(_)) => summary.stx_burn += 1,
+                StacksTransactionEvent::NFTEvent(NFTEventType::NFTTransferEvent(_)) => summary.nft_transfer += 1,
+                StacksTransactionEvent::NFTEvent(NFTEventType::NFTMintEvent(_)) => summary.nft_mint += 1,
+                StacksTransactionEvent::FTEvent(FTEventType::FTTransferEvent(_)) => summary.ft_transfer += 1,
+                StacksTransactionEvent::FTEvent(FTEventType::FTMintEvent(_)) => summary.ft_mint += 1,
+            }
+        });
+        summary
+    }
+
+    /// Match the events in `receipts` against the observers' subscriptions, and hand each observer
+    /// the events it should receive through `send`.  Delivery itself happens on the observers'
+    /// threads, so this returns as soon as the payloads are serialized and handed off.
+    fn dispatch_receipts<F>(&self, receipts: &[StacksTransactionReceipt], mut send: F)
+    where F: FnMut(&EventObserver, Vec<&(Txid, &StacksTransactionEvent)>) -> io::Result<()> {
+
+        let mut dispatch_matrix = DispatchMatrix::new(self.registered_observers.len());
+        for o_i in &self.any_event_observers_lookup {
+            dispatch_matrix.insert_all(*o_i);
+        }
         let mut events: Vec<(Txid, &StacksTransactionEvent)> = vec![];
+        let mut events_receipts: Vec<&StacksTransactionReceipt> = vec![];
+        let mut events_indexes_in_tx: Vec<usize> = vec![];
         let mut i: usize = 0;
-        for receipt in chain_tip.receipts.iter() {
-            let tx_hash = receipt.transaction.txid();
-            for event in receipt.events.iter() {
-                match event {
-                    StacksTransactionEvent::SmartContractEvent(event_data) => {
-                        if let Some(observer_indexes) = self.contract_events_observers_lookup.get(&event_data.key) {
-                            for o_i in observer_indexes {
-                                dispatch_matrix[*o_i as usize].insert(i);
-                            }
+        let mut sender_observers = HashSet::new();
+        EventDispatcher::walk_events(receipts, |receipt, tx_hash, event_index, event| {
+            // a transaction's events are walked in order, so its sender subscriptions are
+            // looked up once, on its first event
+            if event_index == 0 {
+                sender_observers = self.sender_observers(receipt);
+            }
+            match event {
+                StacksTransactionEvent::SmartContractEvent(event_data) => {
+                    if let Some(observer_indexes) = self.contract_events_observers_lookup.get(&event_data.key) {
+                        for o_i in observer_indexes {
+                            dispatch_matrix.insert(*o_i, i);
                         }
-                    },
-                    StacksTransactionEvent::STXEvent(STXEventType::STXTransferEvent(_)) |
-                    StacksTransactionEvent::STXEvent(STXEventType::STXMintEvent(_)) |
-                    StacksTransactionEvent::STXEvent(STXEventType::STXBurnEvent(_)) => {
-                        for o_i in &self.stx_observers_lookup {
-                            dispatch_matrix[*o_i as usize].insert(i);
+                    }
+                    if let Some(predicate_observers) = self.contract_events_predicate_observers_lookup.get(&event_data.key) {
+                        for (o_i, predicate) in predicate_observers {
+                            if predicate.matches(&event_data.value) {
+                                dispatch_matrix.insert(*o_i, i);
+                            }
                         }
-                    },
-                    StacksTransactionEvent::NFTEvent(NFTEventType::NFTTransferEvent(event_data)) => {
-                        self.update_dispatch_matrix_if_observer_subscribed(&event_data.asset_identifier, i, &mut dispatch_matrix);
-                    },
-                    StacksTransactionEvent::NFTEvent(NFTEventType::NFTMintEvent(event_data)) => {
-                        self.update_dispatch_matrix_if_observer_subscribed(&event_data.asset_identifier, i, &mut dispatch_matrix);
-                    },
-                    StacksTransactionEvent::FTEvent(FTEventType::FTTransferEvent(event_data)) => {
-                        self.update_dispatch_matrix_if_observer_subscribed(&event_data.asset_identifier, i, &mut dispatch_matrix);
-                    },
-                    StacksTransactionEvent::FTEvent(FTEventType::FTMintEvent(event_data)) => {
-                        self.update_dispatch_matrix_if_observer_subscribed(&event_data.asset_identifier, i, &mut dispatch_matrix);
-                    },
+                    }
+                    self.update_dispatch_matrix_if_glob_subscribed(&event_data.key.0, i, &mut dispatch_matrix);
+                },
+                StacksTransactionEvent::STXEvent(STXEventType::STXTransferEvent(_)) |
+                StacksTransactionEvent::STXEvent(STXEventType::STXMintEvent(_)) |
+                StacksTransactionEvent::STXEvent(STXEventType::STXBurnEvent(_)) => {
+                    for o_i in &self.stx_observers_lookup {
+                        dispatch_matrix.insert(*o_i, i);
+                    }
+                },
+                StacksTransactionEvent::NFTEvent(NFTEventType::NFTTransferEvent(event_data)) => {
+                    self.update_dispatch_matrix_if_observer_subscribed(&event_data.asset_identifier, i, &mut dispatch_matrix);
+                    for o_i in &self.nft_observers_lookup {
+                        dispatch_matrix.insert(*o_i, i);
+                    }
+                },
+                StacksTransactionEvent::NFTEvent(NFTEventType::NFTMintEvent(event_data)) => {
+                    self.update_dispatch_matrix_if_observer_subscribed(&event_data.asset_identifier, i, &mut dispatch_matrix);
+                    for o_i in &self.nft_observers_lookup {
+                        dispatch_matrix.insert(*o_i, i);
+                    }
+                },
+                StacksTransactionEvent::FTEvent(FTEventType::FTTransferEvent(event_data)) => {
+                    self.update_dispatch_matrix_if_observer_subscribed(&event_data.asset_identifier, i, &mut dispatch_matrix);
+                    for o_i in &self.ft_observers_lookup {
+                        dispatch_matrix.insert(*o_i, i);
+                    }
+                },
+                StacksTransactionEvent::FTEvent(FTEventType::FTMintEvent(event_data)) => {
+                    self.update_dispatch_matrix_if_observer_subscribed(&event_data.asset_identifier, i, &mut dispatch_matrix);
+                    for o_i in &self.ft_observers_lookup {
+                        dispatch_matrix.insert(*o_i, i);
+                    }
+                },
+            }
+            events.push((tx_hash, event));
+            events_receipts.push(receipt);
+            events_indexes_in_tx.push(event_index);
+            for o_i in &sender_observers {
+                dispatch_matrix.insert(*o_i, i);
+            }
+            i += 1;
+        });
+
+        for (observer_id, observer) in self.registered_observers.iter().enumerate() {
+            let mut filtered_events: Vec<&(Txid, &StacksTransactionEvent)> = vec![];
+            for event_id in dispatch_matrix.event_ids(observer_id as u16, events.len()) {
+                if !observer.accepts_receipt(events_receipts[event_id]) {
+                    continue;
                 }
-                events.push((tx_hash, event));
-                for o_i in &self.any_event_observers_lookup {
-                    dispatch_matrix[*o_i as usize].insert(i);
+                if !observer.samples_event(&events[event_id].0, events_indexes_in_tx[event_id]) {
+                    continue;
                 }
-                i += 1;
+                filtered_events.push(&events[event_id]);
+            }
+            if let Err(err) = send(observer, filtered_events) {
+                error!("Event dispatcher failed sending to observer at {}: {:?}", observer.endpoint, err);
             }
         }
+    }
 
+    /// The observers subscribed to a transaction's origin, or to the contract it calls or deploys.
+    fn sender_observers(&self, receipt: &StacksTransactionReceipt) -> HashSet<u16> {
+        let mut observer_indexes = HashSet::new();
+        if self.sender_observers_lookup.is_empty() {
+            return observer_indexes;
+        }
 
-        for (observer_id, filtered_events_ids) in dispatch_matrix.iter().enumerate() {
-            let mut filtered_events: Vec<&(Txid, &StacksTransactionEvent)> = vec![];
-            for event_id in filtered_events_ids {
-                filtered_events.push(&events[*event_id]);
+        let mut principals = vec![PrincipalData::from(receipt.transaction.origin_address())];
+        match &receipt.transaction.payload {
+            TransactionPayload::ContractCall(contract_call) => {
+                principals.push(PrincipalData::Contract(contract_call.to_clarity_contract_id()));
+            },
+            TransactionPayload::SmartContract(smart_contract) => {
+                let issuer = StandardPrincipalData::from(receipt.transaction.origin_address());
+                principals.push(PrincipalData::Contract(QualifiedContractIdentifier::new(issuer, smart_contract.name.clone())));
+            },
+            _ => {}
+        }
+
+        for principal in principals.iter() {
+            if let Some(indexes) = self.sender_observers_lookup.get(principal) {
+                observer_indexes.extend(indexes);
             }
-            self.registered_observers[observer_id].send(filtered_events, chain_tip);
         }
+        observer_indexes
     }
 
-    fn update_dispatch_matrix_if_observer_subscribed(&self, asset_identifier: &AssetIdentifier, event_index: usize, dispatch_matrix: &mut Vec<HashSet<usize>>) {
+    fn update_dispatch_matrix_if_observer_subscribed(&self, asset_identifier: &AssetIdentifier, event_index: usize, dispatch_matrix: &mut DispatchMatrix) {
         if let Some(observer_indexes) = self.assets_observers_lookup.get(asset_identifier) {
             for o_i in observer_indexes {
-                dispatch_matrix[*o_i as usize].insert(event_index);
+                dispatch_matrix.insert(*o_i, event_index);
+            }
+        }
+        self.update_dispatch_matrix_if_glob_subscribed(&asset_identifier.contract_identifier, event_index, dispatch_matrix);
+    }
+
+    fn update_dispatch_matrix_if_glob_subscribed(&self, contract_identifier: &QualifiedContractIdentifier, event_index: usize, dispatch_matrix: &mut DispatchMatrix) {
+        // Only pay for the deployer lookup when some observer registered a glob
+        if self.contracts_glob_observers_lookup.is_empty() {
+            return;
+        }
+        if let Some(observer_indexes) = self.contracts_glob_observers_lookup.get(&contract_identifier.issuer) {
+            for o_i in observer_indexes {
+                dispatch_matrix.insert(*o_i, event_index);
             }
         }
     }
 
+    /// Send a synthetic payload, marked with `"test": true`, to the registered observer at
+    /// `endpoint` through its configured transport.  Lets operators check a new observer's
+    /// plumbing without waiting for a matching on-chain event.
+    pub fn send_test_event(&self, endpoint: &str, payload: serde_json::Value) -> io::Result<()> {
+        let observer = self.registered_observers.iter()
+            .find(|observer| observer.endpoint == endpoint)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No event observer registered at {}", endpoint)))?;
+
+        let test_payload = json!({
+            "test": true,
+            "payload": payload,
+        }).to_string();
+
+        info!("Sending test event to observer at: {}", endpoint);
+        let (result_tx, result_rx) = channel();
+        observer.deliver(DeliveryRequest::Test { payload: test_payload, result: result_tx })?;
+        result_rx.recv()
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, format!("Delivery thread for event observer at {} has exited", endpoint)))?
+    }
+
+    /// The sequence number of the last block dispatched to the observer at `endpoint`.
+    pub fn get_observer_sequence(&self, endpoint: &str) -> Option<u64> {
+        self.registered_observers.iter()
+            .find(|observer| observer.endpoint == endpoint)
+            .map(|observer| observer.sequence.load(Ordering::SeqCst))
+    }
+
+    /// Block until every payload dispatched so far has been handled by the observers' delivery
+    /// threads, i.e. delivered, or given up on and queued.  For tests and graceful shutdown.
+    pub fn flush(&self) {
+        let pending: Vec<_> = self.registered_observers.iter().filter_map(|observer| {
+            let (done_tx, done_rx) = channel();
+            match observer.deliver(DeliveryRequest::Flush(done_tx)) {
+                Ok(()) => Some(done_rx),
+                Err(err) => {
+                    warn!("{}", err);
+                    None
+                }
+            }
+        }).collect();
+
+        for done_rx in pending {
+            let _ = done_rx.recv();
+        }
+    }
+
+    /// Register an observer.  Registering an endpoint that is already registered adds the new
+    /// `events_keys` to the existing observer, which keeps its transport and filters, rather than
+    /// delivering every payload to that endpoint twice.
     pub fn register_observer(&mut self, conf: &EventObserverConfig) {
+        if let Some(existing) = self.registered_observers.iter().position(|observer| observer.endpoint == conf.endpoint) {
+            info!("Event observer at {} is already registered; adding its event keys", conf.endpoint);
+            if parse_observer_headers(&conf.headers).as_ref() != Ok(&self.registered_observers[existing].headers) {
+                warn!("Event observer at {} keeps the headers it was first registered with", conf.endpoint);
+            }
+            self.subscribe_observer(existing as u16, &conf.events_keys);
+            return;
+        }
+
         // let event_observer = EventObserver::new(&conf.address, conf.port);
         info!("Registering event observer at: {}", conf.endpoint);
-        let event_observer = EventObserver { endpoint: conf.endpoint.clone() };
-
+        let headers = match parse_observer_headers(&conf.headers) {
+            Ok(headers) => headers,
+            Err(err) => {
+                error!("Failed to register event observer at {}: {}", conf.endpoint, err);
+                return;
+            }
+        };
+        if !headers.is_empty() {
+            info!("Sending headers to event observer at {}: {}", conf.endpoint, redacted_headers(&headers));
+        }
+        let transport: Box<dyn EventTransport> = match conf.transport {
+            EventTransportType::Http => {
+                let timeout = |ms: u64| if ms > 0 { Some(Duration::from_millis(ms)) } else { None };
+                if conf.tls_skip_verify {
+                    warn!("Not verifying the TLS certificate of event observer at {}", conf.endpoint);
+                }
+                match HttpTransport::new(timeout(conf.connect_timeout_ms), timeout(conf.write_timeout_ms), conf.tls_skip_verify, headers.clone()) {
+                    Ok(transport) => Box::new(transport),
+                    Err(err) => {
+                        error!("Failed to register event observer at {}: {:?}", conf.endpoint, err);
+                        return;
+                    }
+                }
+            },
+            EventTransportType::File => Box::new(FileTransport),
+            EventTransportType::Stdout => Box::new(StdoutTransport),
+        };
         let observer_index = self.registered_observers.len() as u16;
 
-        for event_key_type in conf.events_keys.iter() {
+        let queue = match self.queue_path {
+            Some(ref queue_path) => {
+                let dir = observer_queue_dir(queue_path, &conf.endpoint);
+                match PayloadQueue::open(dir, conf.max_queued_payloads) {
+                    Ok(queue) => Some(queue),
+                    Err(err) => {
+                        error!("Failed to open payload queue for event observer at {}: {:?}", conf.endpoint, err);
+                        None
+                    }
+                }
+            },
+            None => None
+        };
+
+        let queue_dir = queue.as_ref().map(|queue| queue.dir.clone());
+        let last_sequence = match queue_dir {
+            Some(ref queue_dir) => load_sequence(queue_dir).unwrap_or_else(|err| {
+                error!("Failed to load sequence number for event observer at {}: {:?}", conf.endpoint, err);
+                0
+            }),
+            None => 0
+        };
+        let delivery = ObserverDelivery {
+            endpoint: conf.endpoint.clone(),
+            transport,
+            retry_count: conf.retry_count,
+            retry_base_delay_ms: conf.retry_base_delay_ms,
+            queue,
+        };
+        let (delivery_tx, delivery_rx) = channel();
+        thread::Builder::new()
+            .name(format!("event-observer-{}", conf.endpoint))
+            .spawn(move || delivery.run(delivery_rx))
+            .expect("Failed to spawn event observer delivery thread");
+
+        let event_observer = EventObserver {
+            endpoint: conf.endpoint.clone(),
+            tx_outcome_filter: conf.tx_outcome_filter,
+            tx_type_filter: conf.tx_type_filter.clone(),
+            sample_rate: conf.sample_rate,
+            queue_dir,
+            sequence: AtomicU64::new(last_sequence),
+            headers,
+            delivery: delivery_tx,
+        };
+
+        self.subscribe_observer(observer_index, &conf.events_keys);
+        self.registered_observers.push(event_observer);
+    }
+
+    /// Add the observer at `observer_index` to the lookups for each of `events_keys`.
+    fn subscribe_observer(&mut self, observer_index: u16, events_keys: &[EventKeyType]) {
+        for event_key_type in events_keys.iter() {
             match event_key_type {
-                EventKeyType::SmartContractEvent(event_key) => {
+                EventKeyType::SmartContractEvent(event_key, Some(predicate)) => {
+                    let predicate_observers = self.contract_events_predicate_observers_lookup.entry(event_key.clone())
+                        .or_insert_with(Vec::new);
+                    let subscription = (observer_index, predicate.clone());
+                    if !predicate_observers.contains(&subscription) {
+                        predicate_observers.push(subscription);
+                    }
+                },
+                EventKeyType::SmartContractEvent(event_key, None) => {
                     match self.contract_events_observers_lookup.entry(event_key.clone()) {
                         Entry::Occupied(observer_indexes) => {
                             observer_indexes.into_mut().insert(observer_index);
@@ -199,6 +1188,12 @@ impl EventDispatcher {
                 EventKeyType::STXEvent => {
                     self.stx_observers_lookup.insert(observer_index);
                 },
+                EventKeyType::AllFungible => {
+                    self.ft_observers_lookup.insert(observer_index);
+                },
+                EventKeyType::AllNonFungible => {
+                    self.nft_observers_lookup.insert(observer_index);
+                },
                 EventKeyType::AssetEvent(event_key) => {
                     match self.assets_observers_lookup.entry(event_key.clone()) {
                         Entry::Occupied(observer_indexes) => {
@@ -211,13 +1206,731 @@ impl EventDispatcher {
                         }
                     };
                 },
+                EventKeyType::ContractsGlobEvent(deployer) => {
+                    match self.contracts_glob_observers_lookup.entry(deployer.clone()) {
+                        Entry::Occupied(observer_indexes) => {
+                            observer_indexes.into_mut().insert(observer_index);
+                        },
+                        Entry::Vacant(v) => {
+                            let mut observer_indexes = HashSet::new();
+                            observer_indexes.insert(observer_index);
+                            v.insert(observer_indexes);
+                        }
+                    };
+                },
+                EventKeyType::BurnchainOp => {
+                    self.burnchain_ops_observers_lookup.insert(observer_index);
+                },
+                EventKeyType::SenderAddress(principal) => {
+                    self.sender_observers_lookup.entry(principal.clone())
+                        .or_insert_with(HashSet::new)
+                        .insert(observer_index);
+                },
                 EventKeyType::AnyEvent => {
                     self.any_event_observers_lookup.insert(observer_index);
                 },
             }
 
         }
+    }
 
-        self.registered_observers.push(event_observer);
+    /// Remove the observer registered at `endpoint`.  Observers are identified by their position,
+    /// so the lookups are remapped for the observers registered after it to keep receiving their
+    /// events.  Returns false if no observer is registered at `endpoint`.
+    pub fn unregister_observer(&mut self, endpoint: &str) -> bool {
+        let removed = match self.registered_observers.iter().position(|observer| observer.endpoint == endpoint) {
+            Some(position) => position as u16,
+            None => return false
+        };
+        info!("Unregistering event observer at: {}", endpoint);
+        let observer = self.registered_observers.remove(removed as usize);
+
+        // Its undelivered payloads were filtered and addressed for this endpoint only, so once its
+        // delivery thread is done with the queue, drop them along with the observer.
+        if let Some(ref queue_dir) = observer.queue_dir {
+            let (done_tx, done_rx) = channel();
+            if observer.deliver(DeliveryRequest::Flush(done_tx)).is_ok() {
+                let _ = done_rx.recv();
+            }
+            if let Err(err) = fs::remove_dir_all(queue_dir) {
+                error!("Failed to remove payload queue {:?} of event observer at {}: {:?}", queue_dir, endpoint, err);
+            }
+        }
+
+        let remap = |observer_indexes: &HashSet<u16>| -> HashSet<u16> {
+            observer_indexes.iter()
+                .filter(|o_i| **o_i != removed)
+                .map(|o_i| if *o_i > removed { *o_i - 1 } else { *o_i })
+                .collect()
+        };
+
+        for observer_indexes in self.contract_events_observers_lookup.values_mut() {
+            *observer_indexes = remap(observer_indexes);
+        }
+        self.contract_events_observers_lookup.retain(|_, observer_indexes| !observer_indexes.is_empty());
+
+        for predicate_observers in self.contract_events_predicate_observers_lookup.values_mut() {
+            predicate_observers.retain(|(o_i, _)| *o_i != removed);
+            for (o_i, _) in predicate_observers.iter_mut() {
+                if *o_i > removed {
+                    *o_i -= 1;
+                }
+            }
+        }
+        self.contract_events_predicate_observers_lookup.retain(|_, predicate_observers| !predicate_observers.is_empty());
+
+        for observer_indexes in self.assets_observers_lookup.values_mut() {
+            *observer_indexes = remap(observer_indexes);
+        }
+        self.assets_observers_lookup.retain(|_, observer_indexes| !observer_indexes.is_empty());
+
+        for observer_indexes in self.contracts_glob_observers_lookup.values_mut() {
+            *observer_indexes = remap(observer_indexes);
+        }
+        self.contracts_glob_observers_lookup.retain(|_, observer_indexes| !observer_indexes.is_empty());
+
+        for observer_indexes in self.sender_observers_lookup.values_mut() {
+            *observer_indexes = remap(observer_indexes);
+        }
+        self.sender_observers_lookup.retain(|_, observer_indexes| !observer_indexes.is_empty());
+
+        self.stx_observers_lookup = remap(&self.stx_observers_lookup);
+        self.ft_observers_lookup = remap(&self.ft_observers_lookup);
+        self.nft_observers_lookup = remap(&self.nft_observers_lookup);
+        self.burnchain_ops_observers_lookup = remap(&self.burnchain_ops_observers_lookup);
+        self.any_event_observers_lookup = remap(&self.any_event_observers_lookup);
+        true
+    }
+
+    /// Remove every registered observer.
+    pub fn clear_observers(&mut self) {
+        self.registered_observers.clear();
+        self.contract_events_observers_lookup.clear();
+        self.contract_events_predicate_observers_lookup.clear();
+        self.assets_observers_lookup.clear();
+        self.contracts_glob_observers_lookup.clear();
+        self.stx_observers_lookup.clear();
+        self.ft_observers_lookup.clear();
+        self.nft_observers_lookup.clear();
+        self.burnchain_ops_observers_lookup.clear();
+        self.sender_observers_lookup.clear();
+        self.any_event_observers_lookup.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stacks::chainstate::stacks::{CoinbasePayload, StacksPrivateKey, StacksTransaction, TransactionAuth, TransactionVersion};
+    use stacks::chainstate::stacks::events::{STXMintEventData, STXTransferEventData, STXBurnEventData, FTMintEventData, NFTMintEventData, SmartContractEventData};
+    use stacks::vm::types::TupleData;
+
+    /// Records what it is sent, or fails every send while `failing` is set, or rejects every
+    /// send while `rejecting` is set.  `attempts` counts every send.
+    #[derive(Debug, Clone, Default)]
+    struct RecordingTransport {
+        sent: std::sync::Arc<std::sync::Mutex<Vec<serde_json::Value>>>,
+        failing: std::sync::Arc<std::sync::atomic::AtomicBool>,
+        rejecting: std::sync::Arc<std::sync::atomic::AtomicBool>,
+        attempts: std::sync::Arc<AtomicU64>,
+    }
+
+    impl EventTransport for RecordingTransport {
+        fn send_payload(&mut self, endpoint: &str, _path: &str, payload: &[u8]) -> io::Result<()> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(io::Error::new(io::ErrorKind::ConnectionRefused, format!("{} is down", endpoint)));
+            }
+            if self.rejecting.load(Ordering::SeqCst) {
+                return Err(PayloadRejected(format!("{} responded with 400", endpoint)).into_io_error());
+            }
+            self.sent.lock().unwrap().push(serde_json::from_slice(payload).unwrap());
+            Ok(())
+        }
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn observer_config(endpoint: &str, events_keys: Vec<EventKeyType>) -> EventObserverConfig {
+        EventObserverConfig {
+            endpoint: endpoint.to_string(),
+            events_keys,
+            ..EventObserverConfig::default()
+        }
+    }
+
+    #[test]
+    fn unregister_middle_observer_remaps_lookups() {
+        let contract_identifier = QualifiedContractIdentifier::parse("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store").unwrap();
+        let event_key = (contract_identifier, "print".to_string());
+
+        let mut dispatcher = EventDispatcher::new(None);
+        dispatcher.register_observer(&observer_config("localhost:3700", vec![EventKeyType::STXEvent]));
+        dispatcher.register_observer(&observer_config("localhost:3701", vec![EventKeyType::AllFungible, EventKeyType::AnyEvent]));
+        dispatcher.register_observer(&observer_config("localhost:3702", vec![EventKeyType::SmartContractEvent(event_key.clone(), None)]));
+
+        assert!(dispatcher.unregister_observer("localhost:3701"));
+        assert!(!dispatcher.unregister_observer("localhost:3701"));
+
+        let endpoints: Vec<_> = dispatcher.registered_observers.iter().map(|observer| observer.endpoint.clone()).collect();
+        assert_eq!(endpoints, vec!["localhost:3700".to_string(), "localhost:3702".to_string()]);
+
+        let mut expected = HashSet::new();
+        expected.insert(0);
+        assert_eq!(dispatcher.stx_observers_lookup, expected);
+
+        let mut expected = HashSet::new();
+        expected.insert(1);
+        assert_eq!(dispatcher.contract_events_observers_lookup.get(&event_key), Some(&expected));
+
+        assert!(dispatcher.ft_observers_lookup.is_empty());
+        assert!(dispatcher.any_event_observers_lookup.is_empty());
+
+        dispatcher.clear_observers();
+        assert!(dispatcher.registered_observers.is_empty());
+        assert!(dispatcher.stx_observers_lookup.is_empty());
+        assert!(dispatcher.contract_events_observers_lookup.is_empty());
+    }
+
+    #[test]
+    fn register_same_endpoint_merges_keys() {
+        let mut dispatcher = EventDispatcher::new(None);
+        dispatcher.register_observer(&observer_config("localhost:3700", vec![EventKeyType::STXEvent]));
+        dispatcher.register_observer(&observer_config("localhost:3701", vec![EventKeyType::AllFungible]));
+        dispatcher.register_observer(&observer_config("localhost:3700", vec![EventKeyType::STXEvent, EventKeyType::AnyEvent]));
+
+        assert_eq!(dispatcher.registered_observers.len(), 2);
+
+        let mut expected = HashSet::new();
+        expected.insert(0);
+        assert_eq!(dispatcher.stx_observers_lookup, expected);
+        assert_eq!(dispatcher.any_event_observers_lookup, expected);
+
+        let mut expected = HashSet::new();
+        expected.insert(1);
+        assert_eq!(dispatcher.ft_observers_lookup, expected);
+    }
+
+    #[test]
+    fn serialize_non_response_result() {
+        let auth = TransactionAuth::from_p2pkh(&StacksPrivateKey::new()).unwrap();
+        let tx = StacksTransaction::new(TransactionVersion::Testnet, auth, TransactionPayload::Coinbase(CoinbasePayload([0u8; 32])));
+        let mut receipt = StacksTransactionReceipt::from_coinbase(tx);
+        receipt.result = Value::UInt(1);
+
+        let mut dispatcher = EventDispatcher::new(None);
+        dispatcher.register_observer(&observer_config("localhost:3700", vec![EventKeyType::AnyEvent]));
+        let serialized_txs = dispatcher.registered_observers[0].serialize_txs(&[receipt], None);
+
+        let mut raw_result = vec![];
+        Value::UInt(1).consensus_serialize(&mut raw_result).unwrap();
+        assert_eq!(serialized_txs.len(), 1);
+        assert_eq!(serialized_txs[0]["success"], json!(true));
+        assert_eq!(serialized_txs[0]["raw_result"], json!(format!("0x{}", to_hex(&raw_result))));
+    }
+
+    #[test]
+    fn sender_subscription_matches_origin() {
+        let privk = StacksPrivateKey::new();
+        let auth = TransactionAuth::from_p2pkh(&privk).unwrap();
+        let tx = StacksTransaction::new(TransactionVersion::Testnet, auth, TransactionPayload::Coinbase(CoinbasePayload([0u8; 32])));
+        let sender = PrincipalData::from(tx.origin_address());
+        let receipt = StacksTransactionReceipt::from_coinbase(tx);
+
+        let other_auth = TransactionAuth::from_p2pkh(&StacksPrivateKey::new()).unwrap();
+        let other_tx = StacksTransaction::new(TransactionVersion::Testnet, other_auth, TransactionPayload::Coinbase(CoinbasePayload([0u8; 32])));
+        let other_receipt = StacksTransactionReceipt::from_coinbase(other_tx);
+
+        let mut dispatcher = EventDispatcher::new(None);
+        assert!(dispatcher.sender_observers(&receipt).is_empty());
+
+        dispatcher.register_observer(&observer_config("localhost:3700", vec![EventKeyType::STXEvent]));
+        dispatcher.register_observer(&observer_config("localhost:3701", vec![EventKeyType::SenderAddress(sender)]));
+
+        let mut expected = HashSet::new();
+        expected.insert(1);
+        assert_eq!(dispatcher.sender_observers(&receipt), expected);
+        assert!(dispatcher.sender_observers(&other_receipt).is_empty());
+    }
+
+    #[test]
+    fn http_endpoint_urls() {
+        assert_eq!(HttpTransport::url("localhost:3700", "/new_block"), "http://localhost:3700/new_block");
+        assert_eq!(HttpTransport::url("localhost:3700/events/", "/new_block"), "http://localhost:3700/events/new_block");
+        assert_eq!(HttpTransport::url("http://localhost:3700", "/new_block"), "http://localhost:3700/new_block");
+        assert_eq!(HttpTransport::url("https://observer.example.com/hooks", "/new_microblock"), "https://observer.example.com/hooks/new_microblock");
+    }
+
+    #[test]
+    fn observer_headers_are_checked_and_redacted() {
+        let mut raw_headers = HashMap::new();
+        raw_headers.insert("X-Api-Key".to_string(), "secret-key".to_string());
+        raw_headers.insert("Authorization".to_string(), "Bearer secret-token".to_string());
+
+        let headers = parse_observer_headers(&raw_headers).unwrap();
+        assert_eq!(headers, vec![("Authorization".to_string(), "Bearer secret-token".to_string()),
+                                 ("X-Api-Key".to_string(), "secret-key".to_string())]);
+
+        let redacted = redacted_headers(&headers);
+        assert_eq!(redacted, "Authorization: <redacted>, X-Api-Key: <redacted>");
+        assert!(!redacted.contains("secret"));
+
+        raw_headers.insert("X-Injected".to_string(), "a\r\nHost: elsewhere".to_string());
+        assert!(parse_observer_headers(&raw_headers).is_err());
+
+        let mut raw_headers = HashMap::new();
+        raw_headers.insert("Bad Name".to_string(), "value".to_string());
+        assert!(parse_observer_headers(&raw_headers).is_err());
+
+        let mut dispatcher = EventDispatcher::new(None);
+        let mut conf = observer_config("localhost:3700", vec![EventKeyType::AnyEvent]);
+        conf.headers.insert("Authorization".to_string(), "Bearer secret-token".to_string());
+        dispatcher.register_observer(&conf);
+        assert_eq!(dispatcher.registered_observers[0].headers,
+                   vec![("Authorization".to_string(), "Bearer secret-token".to_string())]);
+
+        // neither the observer nor its transport may leak header values through Debug
+        let observer_debug = format!("{:?}", dispatcher.registered_observers[0]);
+        assert!(observer_debug.contains("Authorization: <redacted>"));
+        assert!(!observer_debug.contains("secret-token"));
+
+        let transport = HttpTransport::new(None, None, false, headers).unwrap();
+        let transport_debug = format!("{:?}", transport);
+        assert!(transport_debug.contains("X-Api-Key: <redacted>"));
+        assert!(!transport_debug.contains("secret"));
+    }
+
+    #[test]
+    fn any_event_observer_receives_every_event() {
+        let auth = TransactionAuth::from_p2pkh(&StacksPrivateKey::new()).unwrap();
+        let tx = StacksTransaction::new(TransactionVersion::Testnet, auth, TransactionPayload::Coinbase(CoinbasePayload([0u8; 32])));
+        let recipient = PrincipalData::from(tx.origin_address());
+        let mut receipt = StacksTransactionReceipt::from_coinbase(tx);
+        for amount in 0..1000 {
+            receipt.events.push(StacksTransactionEvent::STXEvent(STXEventType::STXMintEvent(STXMintEventData {
+                recipient: recipient.clone(),
+                amount,
+            })));
+        }
+
+        let mut dispatcher = EventDispatcher::new(None);
+        dispatcher.register_observer(&observer_config("localhost:3700", vec![EventKeyType::AnyEvent]));
+        dispatcher.register_observer(&observer_config("localhost:3701", vec![EventKeyType::STXEvent, EventKeyType::AnyEvent]));
+        dispatcher.register_observer(&observer_config("localhost:3702", vec![EventKeyType::AllFungible]));
+
+        let mut received = HashMap::new();
+        dispatcher.dispatch_receipts(&[receipt.clone()], |observer, filtered_events| {
+            let events: Vec<_> = filtered_events.iter().map(|(_, event)| (*event).clone()).collect();
+            received.insert(observer.endpoint.clone(), events);
+            Ok(())
+        });
+
+        assert_eq!(received["localhost:3700"], receipt.events);
+        assert_eq!(received["localhost:3701"], receipt.events);
+        assert!(received["localhost:3702"].is_empty());
+    }
+
+    #[test]
+    fn parse_event_value_predicates() {
+        let contract_identifier = QualifiedContractIdentifier::parse("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store").unwrap();
+
+        match EventKeyType::from_string("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store::print?action=u1") {
+            Some(EventKeyType::SmartContractEvent(key, Some(predicate))) => {
+                assert_eq!(key, (contract_identifier.clone(), "print".to_string()));
+                assert_eq!(predicate, EventValuePredicate { field: "action".to_string(), expected: "u1".to_string() });
+            },
+            _ => panic!("Expected a smart contract event key with a predicate")
+        }
+
+        // only the first '=' splits the field from the value
+        match EventKeyType::from_string("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store::print?memo=\"a=b\"") {
+            Some(EventKeyType::SmartContractEvent(_, Some(predicate))) => {
+                assert_eq!(predicate, EventValuePredicate { field: "memo".to_string(), expected: "\"a=b\"".to_string() });
+            },
+            _ => panic!("Expected a smart contract event key with a predicate")
+        }
+
+        match EventKeyType::from_string("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store::print") {
+            Some(EventKeyType::SmartContractEvent(key, None)) => assert_eq!(key, (contract_identifier, "print".to_string())),
+            _ => panic!("Expected a smart contract event key without a predicate")
+        }
+
+        assert!(EventKeyType::from_string("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store::print?action").is_none());
+        assert!(EventKeyType::from_string("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store::print?=u1").is_none());
+    }
+
+    #[test]
+    fn event_value_predicate_filters_dispatch() {
+        let contract_identifier = QualifiedContractIdentifier::parse("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store").unwrap();
+        let print_event = |action: Value| StacksTransactionEvent::SmartContractEvent(SmartContractEventData {
+            key: (contract_identifier.clone(), "print".to_string()),
+            value: Value::Tuple(TupleData::from_data(vec![("action".into(), action)]).unwrap()),
+        });
+
+        let auth = TransactionAuth::from_p2pkh(&StacksPrivateKey::new()).unwrap();
+        let tx = StacksTransaction::new(TransactionVersion::Testnet, auth, TransactionPayload::Coinbase(CoinbasePayload([0u8; 32])));
+        let mut receipt = StacksTransactionReceipt::from_coinbase(tx);
+        receipt.events = vec![
+            print_event(Value::UInt(1)),
+            print_event(Value::UInt(2)),
+            print_event(Value::buff_from("swap".as_bytes().to_vec()).unwrap()),
+            // not a tuple, so no field to match
+            StacksTransactionEvent::SmartContractEvent(SmartContractEventData { key: (contract_identifier.clone(), "print".to_string()), value: Value::UInt(1) }),
+        ];
+
+        let mut dispatcher = EventDispatcher::new(None);
+        for (endpoint, key) in [("localhost:3700", "STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store::print?action=u1"),
+                                ("localhost:3701", "STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store::print?action=\"swap\""),
+                                ("localhost:3702", "STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store::print?action=u3"),
+                                ("localhost:3703", "STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store::print")].iter() {
+            dispatcher.register_observer(&observer_config(endpoint, vec![EventKeyType::from_string(key).unwrap()]));
+        }
+
+        let mut received = HashMap::new();
+        dispatcher.dispatch_receipts(&[receipt.clone()], |observer, filtered_events| {
+            let events: Vec<_> = filtered_events.iter().map(|(_, event)| (*event).clone()).collect();
+            received.insert(observer.endpoint.clone(), events);
+            Ok(())
+        });
+
+        assert_eq!(received["localhost:3700"], vec![receipt.events[0].clone()]);
+        assert_eq!(received["localhost:3701"], vec![receipt.events[2].clone()]);
+        assert!(received["localhost:3702"].is_empty());
+        assert_eq!(received["localhost:3703"], receipt.events);
+    }
+
+    #[test]
+    fn parse_contracts_glob() {
+        let issuer = PrincipalData::parse_standard_principal("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A").unwrap();
+        match EventKeyType::from_string("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.*") {
+            Some(EventKeyType::ContractsGlobEvent(principal)) => assert_eq!(principal, issuer),
+            _ => panic!("Expected a contracts glob key")
+        }
+
+        assert!(EventKeyType::from_string("not-a-principal.*").is_none());
+        assert!(EventKeyType::from_string(".*").is_none());
+        assert!(EventKeyType::from_string("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store.*").is_none());
+    }
+
+    #[test]
+    fn contracts_glob_matches_issuer() {
+        let contract_identifier = QualifiedContractIdentifier::parse("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store").unwrap();
+        let token_identifier = QualifiedContractIdentifier::parse("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.token").unwrap();
+        let other_identifier = QualifiedContractIdentifier::parse("ST3CECAKJ4BH08JYY7W53MC81BYDT4YDA5M7S5F53.store").unwrap();
+
+        let auth = TransactionAuth::from_p2pkh(&StacksPrivateKey::new()).unwrap();
+        let tx = StacksTransaction::new(TransactionVersion::Testnet, auth, TransactionPayload::Coinbase(CoinbasePayload([0u8; 32])));
+        let principal = PrincipalData::from(tx.origin_address());
+        let mut receipt = StacksTransactionReceipt::from_coinbase(tx);
+        receipt.events = vec![
+            StacksTransactionEvent::SmartContractEvent(SmartContractEventData { key: (contract_identifier, "print".to_string()), value: Value::Int(1) }),
+            StacksTransactionEvent::FTEvent(FTEventType::FTMintEvent(FTMintEventData {
+                asset_identifier: AssetIdentifier { contract_identifier: token_identifier, asset_name: "token".into() },
+                recipient: principal.clone(),
+                amount: 1,
+            })),
+            StacksTransactionEvent::SmartContractEvent(SmartContractEventData { key: (other_identifier, "print".to_string()), value: Value::Int(2) }),
+            StacksTransactionEvent::STXEvent(STXEventType::STXMintEvent(STXMintEventData { recipient: principal, amount: 1 })),
+        ];
+
+        let mut dispatcher = EventDispatcher::new(None);
+        dispatcher.register_observer(&observer_config("localhost:3700", vec![EventKeyType::from_string("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.*").unwrap()]));
+
+        let mut received = HashMap::new();
+        dispatcher.dispatch_receipts(&[receipt.clone()], |observer, filtered_events| {
+            let events: Vec<_> = filtered_events.iter().map(|(_, event)| (*event).clone()).collect();
+            received.insert(observer.endpoint.clone(), events);
+            Ok(())
+        });
+
+        // events of the issuer's contracts, and neither another issuer's nor STX events
+        assert_eq!(received["localhost:3700"], vec![receipt.events[0].clone(), receipt.events[1].clone()]);
+    }
+
+    #[test]
+    fn summarize_events_counts_each_type() {
+        let contract_identifier = QualifiedContractIdentifier::parse("STGT7GSMZG7EA0TS6MVSKT5JC1DCDFGZWJJZXN8A.store").unwrap();
+        let asset_identifier = AssetIdentifier { contract_identifier: contract_identifier.clone(), asset_name: "token".into() };
+
+        let auth = TransactionAuth::from_p2pkh(&StacksPrivateKey::new()).unwrap();
+        let tx = StacksTransaction::new(TransactionVersion::Testnet, auth, TransactionPayload::Coinbase(CoinbasePayload([0u8; 32])));
+        let principal = PrincipalData::from(tx.origin_address());
+
+        let mut first = StacksTransactionReceipt::from_coinbase(tx.clone());
+        first.events = vec![
+            StacksTransactionEvent::STXEvent(STXEventType::STXMintEvent(STXMintEventData { recipient: principal.clone(), amount: 1 })),
+            StacksTransactionEvent::STXEvent(STXEventType::STXMintEvent(STXMintEventData { recipient: principal.clone(), amount: 2 })),
+            StacksTransactionEvent::STXEvent(STXEventType::STXTransferEvent(STXTransferEventData { sender: principal.clone(), recipient: principal.clone(), amount: 3 })),
+            StacksTransactionEvent::SmartContractEvent(SmartContractEventData { key: (contract_identifier.clone(), "print".to_string()), value: Value::Int(1) }),
+        ];
+        let mut second = StacksTransactionReceipt::from_coinbase(tx);
+        second.events = vec![
+            StacksTransactionEvent::STXEvent(STXEventType::STXBurnEvent(STXBurnEventData { sender: principal.clone(), amount: 4 })),
+            StacksTransactionEvent::FTEvent(FTEventType::FTMintEvent(FTMintEventData { asset_identifier: asset_identifier.clone(), recipient: principal.clone(), amount: 5 })),
+            StacksTransactionEvent::NFTEvent(NFTEventType::NFTMintEvent(NFTMintEventData { asset_identifier, recipient: principal, value: Value::Int(6) })),
+            StacksTransactionEvent::SmartContractEvent(SmartContractEventData { key: (contract_identifier, "print".to_string()), value: Value::Int(2) }),
+        ];
+
+        let mut chain_tip = ChainTip::genesis();
+        assert_eq!(EventDispatcher::summarize_events(&chain_tip), EventSummary::default());
+
+        chain_tip.receipts = vec![first, second];
+        let summary = EventDispatcher::summarize_events(&chain_tip);
+        assert_eq!(summary, EventSummary {
+            smart_contract: 2,
+            stx_transfer: 1,
+            stx_mint: 2,
+            stx_burn: 1,
+            nft_transfer: 0,
+            nft_mint: 1,
+            ft_transfer: 0,
+            ft_mint: 1,
+        });
+        assert_eq!(summary.total(), 8);
+    }
+
+    #[test]
+    fn sequence_survives_restart() {
+        let queue_path = test_dir("event-observer-sequence");
+        let queue_path_str = queue_path.to_str().unwrap().to_string();
+
+        let mut dispatcher = EventDispatcher::new(Some(queue_path_str.clone()));
+        dispatcher.register_observer(&observer_config("localhost:3700", vec![EventKeyType::AnyEvent]));
+        assert_eq!(dispatcher.get_observer_sequence("localhost:3700"), Some(0));
+        assert_eq!(dispatcher.get_observer_sequence("localhost:3701"), None);
+
+        // numbers are handed out on dispatch, whether or not the block is ever delivered
+        assert_eq!(dispatcher.registered_observers[0].next_sequence(), 1);
+        assert_eq!(dispatcher.registered_observers[0].next_sequence(), 2);
+        assert_eq!(dispatcher.get_observer_sequence("localhost:3700"), Some(2));
+
+        let mut dispatcher = EventDispatcher::new(Some(queue_path_str.clone()));
+        dispatcher.register_observer(&observer_config("localhost:3701", vec![EventKeyType::AnyEvent]));
+        dispatcher.register_observer(&observer_config("localhost:3700", vec![EventKeyType::AnyEvent]));
+        assert_eq!(dispatcher.get_observer_sequence("localhost:3700"), Some(2));
+        assert_eq!(dispatcher.get_observer_sequence("localhost:3701"), Some(0));
+        assert_eq!(dispatcher.registered_observers[1].next_sequence(), 3);
+
+        fs::remove_dir_all(&queue_path).unwrap();
+    }
+
+    #[test]
+    fn queue_replays_in_order_and_evicts_oldest() {
+        let dir = test_dir("event-observer-queue");
+        let transport = RecordingTransport::default();
+        let mut delivery = ObserverDelivery {
+            endpoint: "localhost:3700".to_string(),
+            transport: Box::new(transport.clone()),
+            retry_count: 0,
+            retry_base_delay_ms: 0,
+            queue: Some(PayloadQueue::open(dir.clone(), 2).unwrap()),
+        };
+        let block_payload = |height: u64| json!({ "block_height": height }).to_string();
+
+        // while the observer is down, only the newest max_queued_payloads blocks are kept
+        transport.failing.store(true, Ordering::SeqCst);
+        for height in 1..4 {
+            assert!(delivery.deliver_block(height, &format!("{:064x}", height), &block_payload(height)).is_err());
+        }
+        assert_eq!(delivery.queue.as_ref().unwrap().entries().unwrap().len(), 2);
+
+        // once it is back, the queue is replayed oldest first, ahead of the new block, and each
+        // payload is removed once delivered
+        transport.failing.store(false, Ordering::SeqCst);
+        delivery.deliver_block(4, &format!("{:064x}", 4), &block_payload(4)).unwrap();
+        let heights: Vec<_> = transport.sent.lock().unwrap().iter().map(|payload| payload["block_height"].clone()).collect();
+        assert_eq!(heights, vec![json!(2), json!(3), json!(4)]);
+        assert!(delivery.queue.as_ref().unwrap().entries().unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejected_payloads_are_dropped() {
+        let dir = test_dir("event-observer-rejected");
+        let transport = RecordingTransport::default();
+        let mut delivery = ObserverDelivery {
+            endpoint: "localhost:3700".to_string(),
+            transport: Box::new(transport.clone()),
+            retry_count: 3,
+            retry_base_delay_ms: 0,
+            queue: Some(PayloadQueue::open(dir.clone(), 10).unwrap()),
+        };
+        let block_payload = |height: u64| json!({ "block_height": height }).to_string();
+
+        // a transient failure is retried, then queued
+        transport.failing.store(true, Ordering::SeqCst);
+        assert!(delivery.deliver_block(1, &format!("{:064x}", 1), &block_payload(1)).is_err());
+        assert_eq!(transport.attempts.load(Ordering::SeqCst), 4);
+        assert_eq!(delivery.queue.as_ref().unwrap().entries().unwrap().len(), 1);
+
+        // a rejection is neither retried nor queued, and a queued payload that gets rejected on
+        // replay is dropped instead of holding up the blocks behind it
+        transport.failing.store(false, Ordering::SeqCst);
+        transport.rejecting.store(true, Ordering::SeqCst);
+        delivery.deliver_block(2, &format!("{:064x}", 2), &block_payload(2)).unwrap();
+        assert_eq!(transport.attempts.load(Ordering::SeqCst), 6);
+        assert!(delivery.queue.as_ref().unwrap().entries().unwrap().is_empty());
+
+        transport.rejecting.store(false, Ordering::SeqCst);
+        delivery.deliver_block(3, &format!("{:064x}", 3), &block_payload(3)).unwrap();
+        let heights: Vec<_> = transport.sent.lock().unwrap().iter().map(|payload| payload["block_height"].clone()).collect();
+        assert_eq!(heights, vec![json!(3)]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn queue_dir_follows_endpoint() {
+        let queue_path = test_dir("event-observer-queue-dirs");
+        let queue_path_str = queue_path.to_str().unwrap().to_string();
+
+        let mut dispatcher = EventDispatcher::new(Some(queue_path_str.clone()));
+        dispatcher.register_observer(&observer_config("localhost:3700", vec![EventKeyType::AnyEvent]));
+        dispatcher.register_observer(&observer_config("localhost:3701", vec![EventKeyType::AnyEvent]));
+
+        let dir_3700 = observer_queue_dir(&queue_path_str, "localhost:3700");
+        let dir_3701 = observer_queue_dir(&queue_path_str, "localhost:3701");
+        assert_ne!(dir_3700, dir_3701);
+        assert_eq!(dispatcher.registered_observers[0].queue_dir, Some(dir_3700.clone()));
+        assert_eq!(dispatcher.registered_observers[1].queue_dir, Some(dir_3701.clone()));
+
+        // the remaining observer keeps its own directory, and the removed one's is deleted
+        assert!(dispatcher.unregister_observer("localhost:3700"));
+        assert!(!dir_3700.exists());
+        assert_eq!(dispatcher.registered_observers[0].queue_dir, Some(dir_3701.clone()));
+
+        // a new observer registered in the same position does not inherit it
+        dispatcher.register_observer(&observer_config("localhost:3702", vec![EventKeyType::AnyEvent]));
+        assert_eq!(dispatcher.registered_observers[1].queue_dir, Some(observer_queue_dir(&queue_path_str, "localhost:3702")));
+
+        fs::remove_dir_all(&queue_path).unwrap();
+    }
+
+    #[test]
+    fn payloads_carry_schema_version() {
+        let dir = test_dir("event-observer-schema-version");
+        fs::create_dir_all(&dir).unwrap();
+        let out_path = dir.join("payloads");
+
+        let mut dispatcher = EventDispatcher::new(None);
+        let mut conf = observer_config(out_path.to_str().unwrap(), vec![EventKeyType::AnyEvent]);
+        conf.transport = EventTransportType::File;
+        dispatcher.register_observer(&conf);
+
+        let auth = TransactionAuth::from_p2pkh(&StacksPrivateKey::new()).unwrap();
+        let tx = StacksTransaction::new(TransactionVersion::Testnet, auth, TransactionPayload::Coinbase(CoinbasePayload([0u8; 32])));
+        let receipt = StacksTransactionReceipt::from_coinbase(tx);
+        dispatcher.registered_observers[0].send_microblock(vec![], &BlockHeaderHash([1u8; 32]), &BlockHeaderHash([2u8; 32]), &[receipt]).unwrap();
+        dispatcher.flush();
+
+        let payload: serde_json::Value = serde_json::from_str(fs::read_to_string(&out_path).unwrap().trim()).unwrap();
+        assert_eq!(EVENT_PAYLOAD_SCHEMA_VERSION, 4);
+        assert_eq!(payload["schema_version"], json!(EVENT_PAYLOAD_SCHEMA_VERSION));
+        assert_eq!(payload["transactions"][0]["fee"], json!(0));
+        assert_eq!(payload["transactions"][0]["execution_cost"]["runtime"], json!(0));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flush_waits_for_payloads_in_order() {
+        let dir = test_dir("event-observer-flush");
+        fs::create_dir_all(&dir).unwrap();
+        let out_path = dir.join("payloads");
+
+        let mut dispatcher = EventDispatcher::new(None);
+        let mut conf = observer_config(out_path.to_str().unwrap(), vec![EventKeyType::AnyEvent]);
+        conf.transport = EventTransportType::File;
+        dispatcher.register_observer(&conf);
+
+        let num_payloads = 100;
+        for i in 0..num_payloads {
+            dispatcher.registered_observers[0].send_microblock(vec![], &BlockHeaderHash([i as u8; 32]), &BlockHeaderHash([0u8; 32]), &[]).unwrap();
+        }
+        dispatcher.flush();
+
+        // every payload is on disk once flush() returns, in the order it was dispatched
+        let contents = fs::read_to_string(&out_path).unwrap();
+        let hashes: Vec<String> = contents.lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["microblock_hash"].as_str().unwrap().to_string())
+            .collect();
+        let expected: Vec<String> = (0..num_payloads).map(|i| format!("0x{:?}", BlockHeaderHash([i as u8; 32]))).collect();
+        assert_eq!(hashes, expected);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn microblock_announcements_follow_confirmed_stream() {
+        let dir = test_dir("event-observer-microblock-announced");
+        fs::create_dir_all(&dir).unwrap();
+        let out_path = dir.join("payloads");
+
+        let mut dispatcher = EventDispatcher::new(None);
+        let mut conf = observer_config(out_path.to_str().unwrap(), vec![EventKeyType::AnyEvent]);
+        conf.transport = EventTransportType::File;
+        dispatcher.register_observer(&conf);
+
+        let auth = TransactionAuth::from_p2pkh(&StacksPrivateKey::new()).unwrap();
+        let make_tx = |byte: u8| StacksTransaction::new(TransactionVersion::Testnet, auth.clone(), TransactionPayload::Coinbase(CoinbasePayload([byte; 32])));
+        let (tx0, tx1, tx2) = (make_tx(0), make_tx(1), make_tx(2));
+
+        let mb1 = StacksMicroblock::first_unsigned(&BlockHeaderHash([3u8; 32]), vec![tx1.clone()]);
+        let mb2 = StacksMicroblock::from_parent_unsigned(&mb1.header, vec![tx2.clone()]).unwrap();
+
+        // the block confirms only the first microblock of the stream
+        let mut chain_tip = ChainTip::genesis();
+        chain_tip.metadata.block_height = 6;
+        chain_tip.block.txs = vec![tx0.clone()];
+        chain_tip.block.header.parent_microblock = mb1.block_hash();
+        chain_tip.receipts = vec![StacksTransactionReceipt::from_coinbase(tx0.clone()),
+                                  StacksTransactionReceipt::from_coinbase(tx1.clone())];
+        let recipient = PrincipalData::from(tx0.origin_address());
+        for receipt in chain_tip.receipts.iter_mut() {
+            receipt.events.push(StacksTransactionEvent::STXEvent(STXEventType::STXMintEvent(STXMintEventData {
+                recipient: recipient.clone(),
+                amount: 1,
+            })));
+        }
+
+        dispatcher.process_confirmed_microblocks(&chain_tip, &BlockHeaderHash([4u8; 32]), &[mb1.clone()]);
+        dispatcher.process_microblock(&mb2, &BlockHeaderHash([4u8; 32]), 5, &[StacksTransactionReceipt::from_coinbase(tx2.clone())]);
+
+        let expected: HashSet<Txid> = vec![tx1.txid(), tx2.txid()].into_iter().collect();
+        assert_eq!(dispatcher.announced_txids(&mb2.block_hash()), expected);
+        let expected: HashSet<Txid> = vec![tx1.txid()].into_iter().collect();
+        assert_eq!(dispatcher.announced_txids(&mb1.block_hash()), expected);
+        assert!(dispatcher.announced_txids(&BlockHeaderHash([0u8; 32])).is_empty());
+
+        dispatcher.process_chain_tip(&chain_tip);
+        dispatcher.flush();
+
+        let contents = fs::read_to_string(&out_path).unwrap();
+        let block: serde_json::Value = serde_json::from_str(contents.lines().last().unwrap()).unwrap();
+        let flags: Vec<(String, bool)> = block["transactions"].as_array().unwrap().iter()
+            .map(|tx| (tx["txid"].as_str().unwrap().to_string(), tx["microblock_announced"].as_bool().unwrap()))
+            .collect();
+        assert_eq!(flags, vec![(format!("0x{}", tx0.txid()), false), (format!("0x{}", tx1.txid()), true)]);
+
+        // the microblock's events stay in the block payload, flagged like their transaction
+        let event_flags: Vec<(String, bool)> = block["events"].as_array().unwrap().iter()
+            .map(|event| (event["txid"].as_str().unwrap().to_string(), event["microblock_announced"].as_bool().unwrap()))
+            .collect();
+        assert_eq!(event_flags, vec![(format!("0x{:?}", tx0.txid()), false), (format!("0x{:?}", tx1.txid()), true)]);
+
+        // a sibling fork may still confirm the stream...
+        assert_eq!(dispatcher.announced_microblocks.len(), 2);
+
+        // ...until the chain has moved well past it
+        chain_tip.metadata.block_height = 5 + MICROBLOCK_ANNOUNCEMENT_DEPTH + 1;
+        dispatcher.process_chain_tip(&chain_tip);
+        assert!(dispatcher.announced_microblocks.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
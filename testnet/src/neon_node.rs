@@ -55,6 +55,8 @@ enum RelayerDirective {
     ProcessTenure(BurnchainHeaderHash, BurnchainHeaderHash, BlockHeaderHash),
     RunTenure(RegisteredKey, BlockSnapshot),
     RegisterKey(BlockSnapshot),
+    /// Announce a processed burnchain block and its accepted operations to the event observers
+    ProcessBurnBlock(BlockSnapshot, Vec<BlockstackOperationType>),
 }
 
 
@@ -134,7 +136,7 @@ fn inner_process_tenure(
     let receipts = processed_block.1;
     let metadata = processed_block.0;
 
-    dispatcher_announce(&chain_state.blocks_path, dispatcher, metadata.clone(), receipts.clone());
+    dispatcher_announce(chain_state, burn_db, dispatcher, metadata.clone(), receipts.clone());
     Ok((metadata, receipts))
 }
 
@@ -284,8 +286,6 @@ fn spawn_miner_relayer(mut relayer: Relayer, local_peer: LocalPeer,
     let mut bitcoin_controller = BitcoinRegtestController::new_dummy(config);
 
 
-    let blocks_path = chainstate.blocks_path.clone();
-
     let _relayer_handle = thread::spawn(move || {
         while let Ok(mut directive) = relay_channel.recv() {
             match directive {
@@ -295,7 +295,7 @@ fn spawn_miner_relayer(mut relayer: Relayer, local_peer: LocalPeer,
                         .expect("BUG: failure processing network results");
 
                     for (stacks_header, tx_receipts) in block_receipts {
-                        dispatcher_announce(&blocks_path, &mut event_dispatcher, stacks_header, tx_receipts);
+                        dispatcher_announce(&chainstate, &burndb, &mut event_dispatcher, stacks_header, tx_receipts);
                     }
                 },
                 RelayerDirective::ProcessTenure(burn_header_hash, parent_burn_header_hash, block_header_hash) => {
@@ -336,29 +336,47 @@ fn spawn_miner_relayer(mut relayer: Relayer, local_peer: LocalPeer,
                 RelayerDirective::RegisterKey(ref last_burn_block) => {
                     rotate_vrf_and_register(&mut keychain, last_burn_block, &mut bitcoin_controller)
                 },
+                RelayerDirective::ProcessBurnBlock(ref block_snapshot, ref ops) => {
+                    event_dispatcher.process_burn_block(block_snapshot, ops);
+                },
             }
         }
+
+        // the node hung up; let the observers get what was dispatched before exiting
+        event_dispatcher.flush();
     });
 
     Ok(())
 }
 
-fn dispatcher_announce(blocks_path: &str, event_dispatcher: &mut EventDispatcher,
+fn dispatcher_announce(chain_state: &StacksChainState, burn_db: &BurnDB, event_dispatcher: &mut EventDispatcher,
                        metadata: StacksHeaderInfo, receipts: Vec<StacksTransactionReceipt>) {
     let block = {
         let block_path = StacksChainState::get_block_path(
-            blocks_path, 
+            &chain_state.blocks_path, 
             &metadata.burn_header_hash, 
             &metadata.anchored_header.block_hash()).unwrap();
         StacksChainState::consensus_load(&block_path).unwrap()
     };
 
+    let burn_block_height = ChainTip::lookup_burn_block_height(burn_db, &metadata);
     let chain_tip = ChainTip {
         metadata,
         block,
-        receipts
+        receipts,
+        burn_block_height,
     };
 
+    match chain_state.load_confirmed_microblocks(&chain_tip.metadata.burn_header_hash, &chain_tip.metadata.anchored_header.block_hash()) {
+        Ok(Some((parent_index_block_hash, microblocks))) => {
+            event_dispatcher.process_confirmed_microblocks(&chain_tip, &parent_index_block_hash, &microblocks);
+        },
+        Ok(None) => {},
+        Err(e) => {
+            warn!("Failed to load the microblocks confirmed by {}: {:?}", chain_tip.metadata.anchored_header.block_hash(), e);
+        }
+    }
+
     event_dispatcher.process_chain_tip(&chain_tip);
 }
 
@@ -647,8 +665,11 @@ impl InitializedNeonNode {
             .expect("Failed to obtain block snapshot for processed burn block.");
         let block_height = block_snapshot.block_height;
 
+        let mut ops = vec![];
+
         let block_commits = BurnDB::get_block_commits_by_block(&mut burn_tx, block_height, burn_hash)
             .expect("Unexpected BurnDB error fetching block commits");
+        ops.extend(block_commits.iter().cloned().map(BlockstackOperationType::LeaderBlockCommit));
         for op in block_commits.into_iter() {
             if op.txid == block_snapshot.winning_block_txid {
                 last_sortitioned_block = Some((block_snapshot.clone(), op.vtxindex));
@@ -662,6 +683,7 @@ impl InitializedNeonNode {
 
         let key_registers = BurnDB::get_leader_keys_by_block(&mut burn_tx, block_height, burn_hash)
             .expect("Unexpected BurnDB error fetching key registers");
+        ops.extend(key_registers.iter().cloned().map(BlockstackOperationType::LeaderKeyRegister));
         for op in key_registers.into_iter() {
             if op.address == Keychain::address_from_burnchain_signer(&self.burnchain_signer) {
                 // Registered key has been mined
@@ -674,7 +696,18 @@ impl InitializedNeonNode {
             }
         }
 
-        // no-op on UserBurnSupport ops are not supported / produced at this point.
+        // UserBurnSupport ops are not supported / produced at this point, but observers still
+        // get to see them.
+        let user_burns = BurnDB::get_user_burns_by_block(&mut burn_tx, block_height, burn_hash)
+            .expect("Unexpected BurnDB error fetching user burns");
+        ops.extend(user_burns.into_iter().map(BlockstackOperationType::UserBurnSupport));
+
+        // announced from the relayer thread, which owns the event dispatcher
+        ops.sort_by_key(|op| op.vtxindex());
+        if self.relay_channel.send(RelayerDirective::ProcessBurnBlock(block_snapshot.clone(), ops)).is_err() {
+            warn!("Failed to hand burn block {} to the relayer for dispatch", &block_snapshot.burn_header_hash);
+        }
+
         self.last_burn_block = Some(block_snapshot);
 
         (last_sortitioned_block.map(|x| x.0), won_sortition)
@@ -703,7 +736,7 @@ impl NeonGenesisNode {
             Err(err) => panic!("Error while opening chain state at path {}: {:?}", config.get_chainstate_path(), err)
         };
 
-        let mut event_dispatcher = EventDispatcher::new();
+        let mut event_dispatcher = EventDispatcher::new(Some(config.get_event_queue_path()));
         for observer in config.events_observers.iter() {
             event_dispatcher.register_observer(observer);
         }
@@ -31,6 +31,7 @@ pub struct ChainTip {
     pub metadata: StacksHeaderInfo,
     pub block: StacksBlock,
     pub receipts: Vec<StacksTransactionReceipt>,
+    pub burn_block_height: u64,
 }
 
 impl ChainTip {
@@ -39,7 +40,19 @@ impl ChainTip {
         ChainTip {
             metadata: StacksHeaderInfo::genesis(),
             block: StacksBlock::genesis(),
-            receipts: vec![]
+            receipts: vec![],
+            burn_block_height: 0,
+        }
+    }
+
+    /// Height of the burnchain block that the Stacks block described by `metadata` is anchored to.
+    pub fn lookup_burn_block_height(db: &BurnDB, metadata: &StacksHeaderInfo) -> u64 {
+        match BurnDB::get_block_snapshot(db.conn(), &metadata.burn_header_hash) {
+            Ok(Some(snapshot)) => snapshot.block_height,
+            _ => {
+                warn!("No burnchain snapshot found for burn header hash {}", metadata.burn_header_hash);
+                0
+            }
         }
     }
 }
@@ -125,7 +138,7 @@ impl Node {
             Ok(res) => res,
             Err(err) => panic!("Error while opening chain state at path {}: {:?}", config.get_chainstate_path(), err)
         };
-        let mut event_dispatcher = EventDispatcher::new();
+        let mut event_dispatcher = EventDispatcher::new(Some(config.get_event_queue_path()));
 
         for observer in &config.events_observers {
             event_dispatcher.register_observer(observer);
@@ -151,7 +164,7 @@ impl Node {
 
         let keychain = Keychain::default(config.node.seed.clone());
 
-        let mut event_dispatcher = EventDispatcher::new();
+        let mut event_dispatcher = EventDispatcher::new(Some(config.get_event_queue_path()));
 
         for observer in &config.events_observers {
             event_dispatcher.register_observer(observer);
@@ -291,6 +304,7 @@ impl Node {
         let mut last_sortitioned_block = None; 
         let mut won_sortition = false;
         let ops = &burnchain_tip.state_transition.accepted_ops;
+        self.event_dispatcher.process_burn_block(&burnchain_tip.block_snapshot, ops);
 
         for op in ops.iter() {
             match op {
@@ -509,12 +523,24 @@ impl Node {
             StacksChainState::consensus_load(&block_path).unwrap()
         };
 
+        let burn_block_height = ChainTip::lookup_burn_block_height(db, &metadata);
         let chain_tip = ChainTip {
             metadata,
             block,
-            receipts
+            receipts,
+            burn_block_height,
         };
 
+        match self.chain_state.load_confirmed_microblocks(&chain_tip.metadata.burn_header_hash, &chain_tip.metadata.anchored_header.block_hash()) {
+            Ok(Some((parent_index_block_hash, microblocks))) => {
+                self.event_dispatcher.process_confirmed_microblocks(&chain_tip, &parent_index_block_hash, &microblocks);
+            },
+            Ok(None) => {},
+            Err(e) => {
+                warn!("Failed to load the microblocks confirmed by {}: {:?}", chain_tip.metadata.anchored_header.block_hash(), e);
+            }
+        }
+
         self.event_dispatcher.process_chain_tip(&chain_tip);
 
         self.chain_tip = Some(chain_tip.clone());
@@ -527,6 +553,12 @@ impl Node {
         chain_tip
     }
 
+    /// Block until the event observers have been handed every payload dispatched so far.  Call
+    /// before shutting down, since the observers' delivery threads don't outlive the process.
+    pub fn flush_events(&self) {
+        self.event_dispatcher.flush();
+    }
+
     /// Returns the Stacks address of the node
     pub fn get_address(&self) -> StacksAddress {
         self.keychain.get_address()
@@ -134,6 +134,7 @@ impl RunLoop {
         round_index = 1;
         loop {
             if expected_num_rounds == round_index {
+                self.node.flush_events();
                 return;
             }
 